@@ -0,0 +1,365 @@
+use crate::math::Vec2;
+use crate::narrowphase::{penetration, Shape};
+use crate::quadtree::{Bounded, Contact, ObjectId, QuadBox};
+use crate::slab::{Slab, Slot};
+use std::collections::HashMap;
+
+pub trait Broadphase<T: Bounded> {
+    fn insert(&mut self, elem: T) -> ObjectId;
+    fn get(&self, id: ObjectId) -> Option<&T>;
+    fn remove(&mut self, id: ObjectId) -> Option<T>;
+    fn update(&mut self, id: ObjectId, new_box: QuadBox) -> bool;
+    fn query(&self, elem: &impl Bounded) -> Vec<Contact>;
+    fn pairs(&self) -> Vec<(ObjectId, ObjectId)>;
+}
+
+pub struct UniformGrid<T> {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<u32>>,
+    // Non-finite or huge boxes, checked against everything instead of being linked into cells.
+    oversized: Vec<u32>,
+    slab: Slab<T>,
+}
+
+const MAX_CELLS_PER_BOX: i64 = 4096;
+
+pub struct SweepAndPrune<T> {
+    order: Vec<u32>,
+    ranks: Vec<usize>,
+    // Never shrinks on remove, it only bounds how far back a query has to look.
+    max_width: f32,
+    slab: Slab<T>,
+}
+
+fn contact<T: Bounded>(
+    id: ObjectId,
+    u_box: &QuadBox,
+    shape: Shape,
+    other: &Slot<T>,
+) -> Option<Contact> {
+    let other_shape = other.object.as_ref()?.get_shape();
+    penetration(u_box, shape, &other.u_box, other_shape)
+        .map(|pen| Contact::new(Some(id), other.u_box.clone(), pen))
+}
+
+fn ordered(a: ObjectId, b: ObjectId) -> (ObjectId, ObjectId) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+impl<T: Bounded> UniformGrid<T> {
+    pub fn new(cell_size: f32) -> Option<Self> {
+        if !cell_size.is_finite() || cell_size <= 0.0 {
+            return None;
+        }
+        Some(UniformGrid {
+            cell_size,
+            cells: HashMap::new(),
+            oversized: Vec::new(),
+            slab: Slab::new(),
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.slab.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.slab.len() == 0
+    }
+
+    fn cell(&self, point: Vec2) -> (i32, i32) {
+        (
+            (point.x / self.cell_size).floor() as i32,
+            (point.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn cell_range(&self, u_box: &QuadBox) -> Option<((i32, i32), (i32, i32))> {
+        if !u_box.is_finite() {
+            return None;
+        }
+        let min = self.cell(u_box.get_lefttop());
+        let max = self.cell(Vec2 {
+            x: u_box.get_right_x(),
+            y: u_box.get_bottom_y(),
+        });
+        let cells = (max.0 as i64 - min.0 as i64 + 1) * (max.1 as i64 - min.1 as i64 + 1);
+        if cells > MAX_CELLS_PER_BOX {
+            return None;
+        }
+        Some((min, max))
+    }
+
+    fn link(&mut self, index: u32, u_box: &QuadBox) {
+        let Some((min, max)) = self.cell_range(u_box) else {
+            self.oversized.push(index);
+            return;
+        };
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                self.cells.entry((x, y)).or_default().push(index);
+            }
+        }
+    }
+
+    fn unlink(&mut self, index: u32, u_box: &QuadBox) {
+        let Some((min, max)) = self.cell_range(u_box) else {
+            self.oversized.retain(|n| *n != index);
+            return;
+        };
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                if let Some(cell) = self.cells.get_mut(&(x, y)) {
+                    if let Some(pos) = cell.iter().position(|n| *n == index) {
+                        cell.swap_remove(pos);
+                    }
+                    if cell.is_empty() {
+                        self.cells.remove(&(x, y));
+                    }
+                }
+            }
+        }
+    }
+
+    // A pair of overlapping boxes shares every cell under their intersection,
+    // so it is only reported from the cell holding the intersection's top-left corner.
+    fn owns(&self, key: (i32, i32), a: &QuadBox, b: &QuadBox) -> bool {
+        let corner = Vec2 {
+            x: a.get_lefttop().x.max(b.get_lefttop().x),
+            y: a.get_lefttop().y.max(b.get_lefttop().y),
+        };
+        self.cell(corner) == key
+    }
+}
+
+impl<T: Bounded> Broadphase<T> for UniformGrid<T> {
+    fn insert(&mut self, elem: T) -> ObjectId {
+        let u_box = elem.get_box();
+        let id = self.slab.insert(u_box.clone(), elem);
+        self.link(id.index, &u_box);
+        id
+    }
+
+    fn get(&self, id: ObjectId) -> Option<&T> {
+        self.slab.get(id)
+    }
+
+    fn remove(&mut self, id: ObjectId) -> Option<T> {
+        let u_box = self.slab.slot(id)?.u_box.clone();
+        self.unlink(id.index, &u_box);
+        self.slab.remove(id)
+    }
+
+    fn update(&mut self, id: ObjectId, new_box: QuadBox) -> bool {
+        let old_box = match self.slab.slot(id) {
+            Some(x) => x.u_box.clone(),
+            None => return false,
+        };
+        if self.cell_range(&old_box) != self.cell_range(&new_box) {
+            self.unlink(id.index, &old_box);
+            self.link(id.index, &new_box);
+        }
+        self.slab.set_box(id.index, new_box);
+        true
+    }
+
+    fn query(&self, elem: &impl Bounded) -> Vec<Contact> {
+        let mut ret: Vec<Contact> = Vec::new();
+        let u_box = elem.get_box();
+        let shape = elem.get_shape();
+        let Some((min, max)) = self.cell_range(&u_box) else {
+            return self
+                .slab
+                .iter()
+                .filter_map(|(id, _)| contact(id, &u_box, shape, self.slab.item(id.index)))
+                .collect();
+        };
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                let Some(cell) = self.cells.get(&(x, y)) else {
+                    continue;
+                };
+                for n in cell.iter() {
+                    let other_box = self.slab.u_box(*n);
+                    if !self.owns((x, y), &u_box, other_box) {
+                        continue;
+                    }
+                    if let Some(c) = contact(self.slab.id(*n), &u_box, shape, self.slab.item(*n)) {
+                        ret.push(c);
+                    }
+                }
+            }
+        }
+        for n in self.oversized.iter() {
+            if let Some(c) = contact(self.slab.id(*n), &u_box, shape, self.slab.item(*n)) {
+                ret.push(c);
+            }
+        }
+        ret
+    }
+
+    fn pairs(&self) -> Vec<(ObjectId, ObjectId)> {
+        let mut ret: Vec<(ObjectId, ObjectId)> = Vec::new();
+        for (key, cell) in self.cells.iter() {
+            for (i, a) in cell.iter().enumerate() {
+                let a_box = self.slab.u_box(*a);
+                for b in cell[i + 1..].iter() {
+                    let b_box = self.slab.u_box(*b);
+                    if self.owns(*key, a_box, b_box) && a_box.overlaps(b_box) {
+                        ret.push(ordered(self.slab.id(*a), self.slab.id(*b)));
+                    }
+                }
+            }
+        }
+        for (i, a) in self.oversized.iter().enumerate() {
+            for (b, _) in self.slab.iter() {
+                let later = match self.oversized.iter().position(|n| *n == b.index) {
+                    Some(j) => j > i,
+                    None => true,
+                };
+                // Non-finite boxes do not overlap symmetrically, so test in id order.
+                let (first, second) = ordered(self.slab.id(*a), b);
+                let (first_box, second_box) =
+                    (self.slab.u_box(first.index), self.slab.u_box(second.index));
+                if later && first_box.overlaps(second_box) {
+                    ret.push((first, second));
+                }
+            }
+        }
+        // Cells come out of the map in a per-instance order, and the physics
+        // step resolves pairs one after another.
+        ret.sort_unstable();
+        ret
+    }
+}
+
+impl<T: Bounded> Default for SweepAndPrune<T> {
+    fn default() -> Self {
+        SweepAndPrune {
+            order: Vec::new(),
+            ranks: Vec::new(),
+            max_width: 0.0,
+            slab: Slab::new(),
+        }
+    }
+}
+
+impl<T: Bounded> SweepAndPrune<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.slab.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.slab.len() == 0
+    }
+
+    fn left(&self, rank: usize) -> f32 {
+        self.slab.u_box(self.order[rank]).get_lefttop().x
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.order.swap(a, b);
+        self.ranks[self.order[a] as usize] = a;
+        self.ranks[self.order[b] as usize] = b;
+    }
+
+    fn rerank(&mut self, from: usize) {
+        for n in from..self.order.len() {
+            self.ranks[self.order[n] as usize] = n;
+        }
+    }
+
+    // Moved boxes usually stay close to their old rank, so an insertion sort step is enough.
+    fn resort(&mut self, index: u32) {
+        let mut rank = self.ranks[index as usize];
+        while rank > 0 && self.left(rank - 1) > self.left(rank) {
+            self.swap(rank - 1, rank);
+            rank -= 1;
+        }
+        while rank + 1 < self.order.len() && self.left(rank + 1) < self.left(rank) {
+            self.swap(rank, rank + 1);
+            rank += 1;
+        }
+    }
+}
+
+impl<T: Bounded> Broadphase<T> for SweepAndPrune<T> {
+    fn insert(&mut self, elem: T) -> ObjectId {
+        let u_box = elem.get_box();
+        self.max_width = self.max_width.max(u_box.get_size().x);
+        let id = self.slab.insert(u_box, elem);
+        if self.ranks.len() < self.slab.slots().len() {
+            self.ranks.resize(self.slab.slots().len(), 0);
+        }
+        let left = self.slab.u_box(id.index).get_lefttop().x;
+        let rank = self
+            .order
+            .partition_point(|n| self.slab.u_box(*n).get_lefttop().x <= left);
+        self.order.insert(rank, id.index);
+        self.rerank(rank);
+        id
+    }
+
+    fn get(&self, id: ObjectId) -> Option<&T> {
+        self.slab.get(id)
+    }
+
+    fn remove(&mut self, id: ObjectId) -> Option<T> {
+        self.slab.slot(id)?;
+        let rank = self.ranks[id.index as usize];
+        self.order.remove(rank);
+        self.rerank(rank);
+        self.slab.remove(id)
+    }
+
+    fn update(&mut self, id: ObjectId, new_box: QuadBox) -> bool {
+        if self.slab.slot(id).is_none() {
+            return false;
+        }
+        self.max_width = self.max_width.max(new_box.get_size().x);
+        self.slab.set_box(id.index, new_box);
+        self.resort(id.index);
+        true
+    }
+
+    fn query(&self, elem: &impl Bounded) -> Vec<Contact> {
+        let mut ret: Vec<Contact> = Vec::new();
+        let u_box = elem.get_box();
+        let shape = elem.get_shape();
+        let from = u_box.get_lefttop().x - self.max_width;
+        let start = self
+            .order
+            .partition_point(|n| self.slab.u_box(*n).get_lefttop().x < from);
+        for n in self.order[start..].iter() {
+            let other_box = self.slab.u_box(*n);
+            if other_box.get_lefttop().x > u_box.get_right_x() {
+                break;
+            }
+            if let Some(c) = contact(self.slab.id(*n), &u_box, shape, self.slab.item(*n)) {
+                ret.push(c);
+            }
+        }
+        ret
+    }
+
+    fn pairs(&self) -> Vec<(ObjectId, ObjectId)> {
+        let mut ret: Vec<(ObjectId, ObjectId)> = Vec::new();
+        for (rank, a) in self.order.iter().enumerate() {
+            let a_box = self.slab.u_box(*a);
+            for b in self.order[rank + 1..].iter() {
+                let b_box = self.slab.u_box(*b);
+                if b_box.get_lefttop().x > a_box.get_right_x() {
+                    break;
+                }
+                if a_box.overlaps(b_box) {
+                    ret.push(ordered(self.slab.id(*a), self.slab.id(*b)));
+                }
+            }
+        }
+        ret
+    }
+}
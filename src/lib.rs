@@ -1,8 +1,13 @@
-#![feature(extract_if)]
 #![allow(dead_code, unused_variables)]
+pub mod broadphase;
+pub mod math;
+pub mod narrowphase;
 pub mod objects;
 pub mod physics;
 pub mod quadtree;
+#[cfg(feature = "render")]
+pub mod render;
+mod slab;
 pub use rand::Rng;
 
 pub fn gen_vec_of_objects(
@@ -13,11 +18,11 @@ pub fn gen_vec_of_objects(
     max_rec_width: u32,
     min_rec_height: u32,
     max_rec_height: u32,
-) -> Vec<Box<dyn quadtree::TreeObject>> {
-    let mut ret: Vec<Box<dyn quadtree::TreeObject>> = Vec::new();
+) -> Vec<objects::Rectangle> {
+    let mut ret: Vec<objects::Rectangle> = Vec::new();
     let mut rng = rand::thread_rng();
     for n in 0..size {
-        ret.push(Box::new(
+        ret.push(
             objects::RectangleBuilder::new()
                 .coordinate(
                     rng.gen_range(0..width) as f32,
@@ -28,7 +33,7 @@ pub fn gen_vec_of_objects(
                     rng.gen_range(min_rec_height..max_rec_height) as f32,
                 )
                 .build(),
-        ));
+        );
     }
     ret
 }
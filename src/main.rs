@@ -1,7 +1,9 @@
 #![allow(dead_code, unused_variables)]
+use my_rusted_balls::math::Vec2;
 use my_rusted_balls::objects::*;
 use my_rusted_balls::physics::*;
 use my_rusted_balls::quadtree::*;
+use my_rusted_balls::render::*;
 use raylib::prelude::*;
 
 fn main() {
@@ -27,25 +29,39 @@ fn main() {
         MAX_REC_HEIGHT,
     );
     for n in elems.iter() {
-        tree.add(n);
+        tree.add(n.clone());
     }
 
     let move_elem = Box::new(
         CircleBuilder::new()
             .coordinate(100.0, 100.0)
             .radius(30.0)
-            .acel(Vector2 {
+            .acel(Vec2 {
                 x: X_AXIS_ACEL,
                 y: Y_AXIS_ACEL,
             })
             .color(Color::RED)
+            .body(BodyProperties::rubber())
             .build(),
     );
-    let move_elems = &mut vec![move_elem];
+    let steel_elem = Box::new(
+        CircleBuilder::new()
+            .coordinate(700.0, 100.0)
+            .radius(20.0)
+            .acel(Vec2 {
+                x: -X_AXIS_ACEL,
+                y: Y_AXIS_ACEL,
+            })
+            .color(Color::DARKGRAY)
+            .body(BodyProperties::steel())
+            .build(),
+    );
+    let move_elems = &mut [move_elem, steel_elem];
 
     let mut phy = PhysicsModel::new(
         BaseMovementModel {},
         BaseCollisionModel {},
+        tree,
         WINDOW_WIDTH as f32,
         WINDOW_HEIGHT as f32,
         MODEL_PERIOD,
@@ -55,12 +71,26 @@ fn main() {
         .title("My Rusted Balls")
         .build();
 
+    let mut debug_draw = false;
+    let mut traced: Vec<u32> = Vec::new();
     while !rl.window_should_close() {
+        if rl.is_key_pressed(KeyboardKey::KEY_D) {
+            debug_draw = !debug_draw;
+        }
         let mut d = rl.begin_drawing(&thread);
-        //phy.get_m_model().set_mouse_position(d.get_mouse_position());
+        //phy.get_m_model().set_mouse_position(d.get_mouse_position().into());
         d.clear_background(Color::WHITE);
-        tree.draw_tree(&mut d);
-        phy.run(move_elems, &mut tree);
-        move_elems[0].draw(&mut d);
+        phy.get_world().draw_tree(&mut d);
+        if debug_draw {
+            traced.clear();
+            for n in move_elems.iter() {
+                phy.get_world().query_traced(n, &mut traced);
+            }
+            phy.get_world().draw_debug(&mut d, &traced);
+        }
+        phy.run(move_elems);
+        for n in move_elems.iter() {
+            n.draw(&mut d);
+        }
     }
 }
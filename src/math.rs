@@ -0,0 +1,101 @@
+#[cfg(feature = "render")]
+use raylib::prelude::Vector2;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Vec2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Vec2 {
+    pub const fn new(x: f32, y: f32) -> Self {
+        Vec2 { x, y }
+    }
+    pub const fn zero() -> Self {
+        Vec2 { x: 0.0, y: 0.0 }
+    }
+    pub fn length(&self) -> f32 {
+        self.length_sqr().sqrt()
+    }
+    pub fn length_sqr(&self) -> f32 {
+        self.x * self.x + self.y * self.y
+    }
+    pub fn dot(&self, other: Vec2) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+    pub fn normalized(&self) -> Vec2 {
+        let length = self.length();
+        if length == 0.0 {
+            return *self;
+        }
+        *self / length
+    }
+}
+
+impl Add for Vec2 {
+    type Output = Vec2;
+    fn add(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Vec2 {
+    type Output = Vec2;
+    fn sub(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Mul<f32> for Vec2 {
+    type Output = Vec2;
+    fn mul(self, value: f32) -> Vec2 {
+        Vec2::new(self.x * value, self.y * value)
+    }
+}
+
+impl Div<f32> for Vec2 {
+    type Output = Vec2;
+    fn div(self, value: f32) -> Vec2 {
+        Vec2::new(self.x / value, self.y / value)
+    }
+}
+
+impl Neg for Vec2 {
+    type Output = Vec2;
+    fn neg(self) -> Vec2 {
+        Vec2::new(-self.x, -self.y)
+    }
+}
+
+impl AddAssign for Vec2 {
+    fn add_assign(&mut self, other: Vec2) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vec2 {
+    fn sub_assign(&mut self, other: Vec2) {
+        *self = *self - other;
+    }
+}
+
+#[cfg(feature = "render")]
+impl From<Vector2> for Vec2 {
+    fn from(value: Vector2) -> Self {
+        Vec2::new(value.x, value.y)
+    }
+}
+
+#[cfg(feature = "render")]
+impl From<Vec2> for Vector2 {
+    fn from(value: Vec2) -> Self {
+        Vector2 {
+            x: value.x,
+            y: value.y,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::math::Vec2;
+use crate::quadtree::QuadBox;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shape {
+    #[default]
+    Aabb,
+    // The circle inscribed in the object's box, so the box alone is enough to place it.
+    Circle,
+}
+
+pub fn penetration(
+    u_box: &QuadBox,
+    shape: Shape,
+    other_box: &QuadBox,
+    other_shape: Shape,
+) -> Option<Vec2> {
+    if !u_box.intersects(other_box) {
+        return None;
+    }
+    match (shape, other_shape) {
+        (Shape::Aabb, Shape::Aabb) => {
+            u_box.pen_vector(other_box, &u_box.minkowski_difference(other_box))
+        }
+        (Shape::Circle, Shape::Circle) => circle_circle(
+            u_box.get_center(),
+            radius(u_box),
+            other_box.get_center(),
+            radius(other_box),
+        ),
+        (Shape::Circle, Shape::Aabb) => circle_aabb(u_box.get_center(), radius(u_box), other_box),
+        (Shape::Aabb, Shape::Circle) => {
+            circle_aabb(other_box.get_center(), radius(other_box), u_box).map(|x| -x)
+        }
+    }
+}
+
+fn radius(u_box: &QuadBox) -> f32 {
+    u_box.get_size().x.min(u_box.get_size().y) / 2.0
+}
+
+fn circle_circle(center: Vec2, radius: f32, other_center: Vec2, other_radius: f32) -> Option<Vec2> {
+    let delta = center - other_center;
+    let dist = delta.length();
+    let reach = radius + other_radius;
+    if dist >= reach {
+        return None;
+    }
+    if dist <= f32::EPSILON {
+        return Some(Vec2 { x: 0.0, y: -reach });
+    }
+    Some(delta * ((reach - dist) / dist))
+}
+
+fn circle_aabb(center: Vec2, radius: f32, u_box: &QuadBox) -> Option<Vec2> {
+    let left = u_box.get_lefttop().x;
+    let top = u_box.get_lefttop().y;
+    let right = u_box.get_right_x();
+    let bottom = u_box.get_bottom_y();
+    let closest = Vec2 {
+        x: center.x.max(left).min(right),
+        y: center.y.max(top).min(bottom),
+    };
+    let delta = center - closest;
+    let dist = delta.length();
+    if dist > f32::EPSILON {
+        if dist >= radius {
+            return None;
+        }
+        return Some(delta * ((radius - dist) / dist));
+    }
+    // The centre is inside the box, leave through the nearest face.
+    let faces = [
+        (center.x - left, Vec2 { x: -1.0, y: 0.0 }),
+        (right - center.x, Vec2 { x: 1.0, y: 0.0 }),
+        (center.y - top, Vec2 { x: 0.0, y: -1.0 }),
+        (bottom - center.y, Vec2 { x: 0.0, y: 1.0 }),
+    ];
+    let (depth, normal) = faces
+        .into_iter()
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .unwrap();
+    Some(normal * (depth + radius))
+}
@@ -1,55 +1,127 @@
+use crate::math::Vec2;
+use crate::narrowphase::Shape;
 use crate::quadtree::*;
+#[cfg(feature = "render")]
+use crate::render::Drawable;
+#[cfg(feature = "render")]
 use raylib::{
     color::Color,
-    prelude::{RaylibDraw, RaylibDrawHandle, Vector2},
+    prelude::{RaylibDraw, RaylibDrawHandle},
 };
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 pub trait MovingObject {
-    fn set_coordinate(&mut self, new_vec: Vector2);
+    fn set_coordinate(&mut self, new_vec: Vec2);
+    #[cfg(feature = "render")]
     fn set_color(&mut self, color: Color);
-    fn set_speed(&mut self, speed: Vector2);
-    fn set_acel(&mut self, acel: Vector2);
+    fn set_speed(&mut self, speed: Vec2);
+    fn set_acel(&mut self, acel: Vec2);
 
-    fn update_coordinate(&mut self, new_vec: Vector2);
-    fn update_speed(&mut self, speed: Vector2);
-    fn update_acel(&mut self, acel: Vector2);
+    fn update_coordinate(&mut self, new_vec: Vec2);
+    fn update_speed(&mut self, speed: Vec2);
+    fn update_acel(&mut self, acel: Vec2);
 
-    fn get_coordinate(&self) -> Vector2;
-    fn get_speed(&self) -> Vector2;
-    fn get_acel(&self) -> Vector2;
+    fn get_coordinate(&self) -> Vec2;
+    fn get_speed(&self) -> Vec2;
+    fn get_acel(&self) -> Vec2;
+
+    fn set_body(&mut self, body: BodyProperties);
+    fn get_body(&self) -> BodyProperties;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BodyProperties {
+    pub mass: f32,
+    pub restitution: f32,
+    pub static_friction: f32,
+    pub dynamic_friction: f32,
+}
+
+impl Default for BodyProperties {
+    fn default() -> Self {
+        BodyProperties {
+            mass: 1.0,
+            restitution: 1.0,
+            static_friction: 0.0,
+            dynamic_friction: 0.0,
+        }
+    }
+}
+
+impl BodyProperties {
+    pub fn rubber() -> Self {
+        BodyProperties {
+            mass: 1.0,
+            restitution: 0.8,
+            static_friction: 0.9,
+            dynamic_friction: 0.7,
+        }
+    }
+    pub fn steel() -> Self {
+        BodyProperties {
+            mass: 7.8,
+            restitution: 0.6,
+            static_friction: 0.6,
+            dynamic_friction: 0.4,
+        }
+    }
+    pub fn ice() -> Self {
+        BodyProperties {
+            mass: 0.9,
+            restitution: 0.3,
+            static_friction: 0.05,
+            dynamic_friction: 0.02,
+        }
+    }
+    pub fn inv_mass(&self) -> f32 {
+        if self.mass > 0.0 {
+            1.0 / self.mass
+        } else {
+            0.0
+        }
+    }
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Rectangle {
-    coordinate: Vector2,
+    coordinate: Vec2,
     width: f32,
     height: f32,
-    speed: Vector2,
-    acel: Vector2,
+    speed: Vec2,
+    acel: Vec2,
     name: Option<String>,
+    #[cfg(feature = "render")]
     color: Color,
+    body: BodyProperties,
 }
 
 pub struct RectangleBuilder {
-    coordinate: Vector2,
+    coordinate: Vec2,
     width: f32,
     height: f32,
-    speed: Vector2,
-    acel: Vector2,
+    speed: Vec2,
+    acel: Vec2,
     name: Option<String>,
+    #[cfg(feature = "render")]
     color: Color,
+    body: BodyProperties,
 }
 
 impl RectangleBuilder {
     pub fn new() -> RectangleBuilder {
         RectangleBuilder {
-            coordinate: Vector2 { x: 0.0, y: 0.0 },
+            coordinate: Vec2 { x: 0.0, y: 0.0 },
             width: 0.0,
             height: 0.0,
-            speed: Vector2 { x: 0.0, y: 0.0 },
-            acel: Vector2 { x: 0.0, y: 0.0 },
+            speed: Vec2 { x: 0.0, y: 0.0 },
+            acel: Vec2 { x: 0.0, y: 0.0 },
             name: None,
+            #[cfg(feature = "render")]
             color: Color::BLACK,
+            body: BodyProperties::default(),
         }
     }
     pub fn coordinate(mut self, x: f32, y: f32) -> RectangleBuilder {
@@ -66,15 +138,20 @@ impl RectangleBuilder {
         self.name = Some(name.to_string());
         self
     }
+    #[cfg(feature = "render")]
     pub fn color(mut self, color: Color) -> RectangleBuilder {
         self.color = color;
         self
     }
-    pub fn speed(mut self, speed: Vector2) -> RectangleBuilder {
+    pub fn body(mut self, body: BodyProperties) -> RectangleBuilder {
+        self.body = body;
+        self
+    }
+    pub fn speed(mut self, speed: Vec2) -> RectangleBuilder {
         self.speed = speed;
         self
     }
-    pub fn acel(mut self, acel: Vector2) -> RectangleBuilder {
+    pub fn acel(mut self, acel: Vec2) -> RectangleBuilder {
         self.acel = acel;
         self
     }
@@ -87,7 +164,9 @@ impl RectangleBuilder {
             speed: self.speed,
             acel: self.acel,
             name: self.name,
+            #[cfg(feature = "render")]
             color: self.color,
+            body: self.body,
         }
     }
 }
@@ -106,7 +185,8 @@ impl std::fmt::Display for Rectangle {
     }
 }
 
-impl TreeObject for Rectangle {
+#[cfg(feature = "render")]
+impl Drawable for Rectangle {
     fn draw(&self, draw_handler: &mut RaylibDrawHandle) {
         let rec = raylib::prelude::Rectangle {
             x: self.coordinate.x,
@@ -123,6 +203,9 @@ impl TreeObject for Rectangle {
             Color::BLACK,
         );
     }
+}
+
+impl Bounded for Rectangle {
     fn get_box(&self) -> QuadBox {
         QuadBox::new(
             self.coordinate.x,
@@ -134,67 +217,82 @@ impl TreeObject for Rectangle {
 }
 
 impl MovingObject for Rectangle {
+    #[cfg(feature = "render")]
     fn set_color(&mut self, color: Color) {
         self.color = color;
     }
-    fn set_coordinate(&mut self, new_vec: Vector2) {
+    fn set_coordinate(&mut self, new_vec: Vec2) {
         self.coordinate.x = new_vec.x;
         self.coordinate.y = new_vec.y;
     }
-    fn set_acel(&mut self, acel: Vector2) {
+    fn set_acel(&mut self, acel: Vec2) {
         self.acel = acel;
     }
-    fn set_speed(&mut self, speed: Vector2) {
+    fn set_speed(&mut self, speed: Vec2) {
         self.speed = speed;
     }
 
-    fn update_coordinate(&mut self, new_vec: Vector2) {
+    fn update_coordinate(&mut self, new_vec: Vec2) {
         self.coordinate.x += new_vec.x;
         self.coordinate.y += new_vec.y;
     }
-    fn update_acel(&mut self, acel: Vector2) {
+    fn update_acel(&mut self, acel: Vec2) {
         self.acel += acel;
     }
-    fn update_speed(&mut self, speed: Vector2) {
+    fn update_speed(&mut self, speed: Vec2) {
         self.speed += speed;
     }
 
-    fn get_acel(&self) -> Vector2 {
+    fn get_acel(&self) -> Vec2 {
         self.acel
     }
-    fn get_coordinate(&self) -> Vector2 {
+    fn get_coordinate(&self) -> Vec2 {
         self.coordinate
     }
-    fn get_speed(&self) -> Vector2 {
+    fn get_speed(&self) -> Vec2 {
         self.speed
     }
+
+    fn set_body(&mut self, body: BodyProperties) {
+        self.body = body;
+    }
+    fn get_body(&self) -> BodyProperties {
+        self.body
+    }
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Circle {
-    pub coordinate: Vector2,
-    pub acel: Vector2,
-    pub speed: Vector2,
+    pub coordinate: Vec2,
+    pub acel: Vec2,
+    pub speed: Vec2,
     pub radius: f32,
+    #[cfg(feature = "render")]
     pub color: Color,
+    pub body: BodyProperties,
     name: Option<String>,
 }
 pub struct CircleBuilder {
-    pub coordinate: Vector2,
-    pub acel: Vector2,
-    pub speed: Vector2,
+    pub coordinate: Vec2,
+    pub acel: Vec2,
+    pub speed: Vec2,
     pub radius: f32,
+    #[cfg(feature = "render")]
     pub color: Color,
+    pub body: BodyProperties,
     name: Option<String>,
 }
 impl CircleBuilder {
     pub fn new() -> CircleBuilder {
         CircleBuilder {
-            coordinate: Vector2 { x: 0.0, y: 0.0 },
-            acel: Vector2 { x: 0.0, y: 0.0 },
-            speed: Vector2 { x: 0.0, y: 0.0 },
+            coordinate: Vec2 { x: 0.0, y: 0.0 },
+            acel: Vec2 { x: 0.0, y: 0.0 },
+            speed: Vec2 { x: 0.0, y: 0.0 },
             radius: 0.0,
+            #[cfg(feature = "render")]
             color: Color::RED,
+            body: BodyProperties::default(),
             name: None,
         }
     }
@@ -207,11 +305,11 @@ impl CircleBuilder {
         self.name = Some(name.to_string());
         self
     }
-    pub fn acel(mut self, acel: Vector2) -> CircleBuilder {
+    pub fn acel(mut self, acel: Vec2) -> CircleBuilder {
         self.acel = acel;
         self
     }
-    pub fn speed(mut self, speed: Vector2) -> CircleBuilder {
+    pub fn speed(mut self, speed: Vec2) -> CircleBuilder {
         self.speed = speed;
         self
     }
@@ -219,17 +317,24 @@ impl CircleBuilder {
         self.radius = radius;
         self
     }
+    #[cfg(feature = "render")]
     pub fn color(mut self, color: Color) -> CircleBuilder {
         self.color = color;
         self
     }
+    pub fn body(mut self, body: BodyProperties) -> CircleBuilder {
+        self.body = body;
+        self
+    }
     pub fn build(self) -> Circle {
         Circle {
             coordinate: self.coordinate,
             acel: self.acel,
             speed: self.speed,
             radius: self.radius,
+            #[cfg(feature = "render")]
             color: self.color,
+            body: self.body,
             name: self.name,
         }
     }
@@ -248,7 +353,8 @@ impl std::fmt::Display for Circle {
     }
 }
 
-impl TreeObject for Circle {
+#[cfg(feature = "render")]
+impl Drawable for Circle {
     fn draw(&self, draw_handler: &mut RaylibDrawHandle) {
         draw_handler.draw_text(
             &self.name.clone().unwrap_or("".to_string()),
@@ -264,6 +370,9 @@ impl TreeObject for Circle {
             self.color,
         );
     }
+}
+
+impl Bounded for Circle {
     fn get_box(&self) -> QuadBox {
         QuadBox::new(
             self.coordinate.x - self.radius,
@@ -272,43 +381,54 @@ impl TreeObject for Circle {
             self.radius * 2.0,
         )
     }
+    fn get_shape(&self) -> Shape {
+        Shape::Circle
+    }
 }
 
 impl MovingObject for Circle {
+    #[cfg(feature = "render")]
     fn set_color(&mut self, color: Color) {
         self.color = color;
     }
-    fn set_coordinate(&mut self, new_vec: Vector2) {
+    fn set_coordinate(&mut self, new_vec: Vec2) {
         self.coordinate.x = new_vec.x + self.radius;
         self.coordinate.y = new_vec.y + self.radius;
     }
-    fn set_acel(&mut self, acel: Vector2) {
+    fn set_acel(&mut self, acel: Vec2) {
         self.acel = acel;
     }
-    fn set_speed(&mut self, speed: Vector2) {
+    fn set_speed(&mut self, speed: Vec2) {
         self.speed = speed;
     }
-    fn update_coordinate(&mut self, new_vec: Vector2) {
+    fn update_coordinate(&mut self, new_vec: Vec2) {
         self.coordinate.x += new_vec.x;
         self.coordinate.y += new_vec.y;
     }
-    fn update_acel(&mut self, acel: Vector2) {
+    fn update_acel(&mut self, acel: Vec2) {
         self.acel += acel;
     }
-    fn update_speed(&mut self, speed: Vector2) {
+    fn update_speed(&mut self, speed: Vec2) {
         self.speed += speed
     }
 
-    fn get_acel(&self) -> Vector2 {
+    fn get_acel(&self) -> Vec2 {
         self.acel
     }
-    fn get_coordinate(&self) -> Vector2 {
-        Vector2 {
+    fn get_coordinate(&self) -> Vec2 {
+        Vec2 {
             x: self.coordinate.x - self.radius,
             y: self.coordinate.y - self.radius,
         }
     }
-    fn get_speed(&self) -> Vector2 {
+    fn get_speed(&self) -> Vec2 {
         self.speed
     }
+
+    fn set_body(&mut self, body: BodyProperties) {
+        self.body = body;
+    }
+    fn get_body(&self) -> BodyProperties {
+        self.body
+    }
 }
@@ -1,7 +1,9 @@
-use crate::quadtree::TreeObject;
-use crate::{objects::MovingObject, quadtree};
-use raylib::math::Vector2;
-use std::time::SystemTime;
+use crate::broadphase::{Broadphase, SweepAndPrune};
+use crate::math::Vec2;
+use crate::narrowphase::penetration;
+use crate::objects::{BodyProperties, MovingObject};
+use crate::quadtree::{Bounded, Contact, ObjectId, QuadBox};
+use std::time::Instant;
 
 pub struct BaseMovementModel;
 pub struct BaseCollisionModel;
@@ -14,27 +16,69 @@ pub trait CollisionModel {
     fn process_collision(
         &mut self,
         object: &mut Box<impl MovingObject>,
-        pen_vectors: &Vec<Option<Vector2>>,
+        contacts: &[Contact],
+        time_delta: f32,
+    );
+    fn process_pair(
+        &mut self,
+        object: &mut Box<impl MovingObject>,
+        other: &mut Box<impl MovingObject>,
+        contact: &Contact,
         time_delta: f32,
     );
 }
 
+pub struct SymplecticEuler;
+pub struct VelocityVerlet;
+pub struct PositionVerlet;
+pub struct Rk4;
+
 impl MovementModel for BaseMovementModel {
+    fn process_movement(&mut self, object: &mut Box<impl MovingObject>, time_delta: f32) {
+        VelocityVerlet {}.process_movement(object, time_delta);
+    }
+}
+
+impl MovementModel for SymplecticEuler {
+    fn process_movement(&mut self, object: &mut Box<impl MovingObject>, time_delta: f32) {
+        object.update_speed(object.get_acel() * time_delta);
+        object.update_coordinate(object.get_speed() * time_delta);
+    }
+}
+
+impl MovementModel for VelocityVerlet {
     fn process_movement(&mut self, object: &mut Box<impl MovingObject>, time_delta: f32) {
         let speed = object.get_speed();
         let acel = object.get_acel();
+        object.update_coordinate(speed * time_delta + acel * (time_delta * time_delta / 2.0));
+        object.update_speed((acel + object.get_acel()) * (time_delta / 2.0));
+    }
+}
+
+impl MovementModel for PositionVerlet {
+    fn process_movement(&mut self, object: &mut Box<impl MovingObject>, time_delta: f32) {
+        object.update_coordinate(object.get_speed() * (time_delta / 2.0));
         object.update_speed(object.get_acel() * time_delta);
-        object.set_acel(acel);
-        let delta_y = speed.y * time_delta + acel.y * time_delta.powf(2.0) / 2.0;
-        object.update_coordinate(Vector2 {
-            x: speed.x * time_delta,
-            y: delta_y,
-        });
+        object.update_coordinate(object.get_speed() * (time_delta / 2.0));
+    }
+}
+
+impl MovementModel for Rk4 {
+    fn process_movement(&mut self, object: &mut Box<impl MovingObject>, time_delta: f32) {
+        let half = time_delta / 2.0;
+        let speed = object.get_speed();
+        let acel = object.get_acel();
+        let k1 = (speed, acel);
+        let k2 = (speed + k1.1 * half, acel);
+        let k3 = (speed + k2.1 * half, acel);
+        let k4 = (speed + k3.1 * time_delta, acel);
+        object.update_coordinate((k1.0 + (k2.0 + k3.0) * 2.0 + k4.0) * (time_delta / 6.0));
+        object.update_speed((k1.1 + (k2.1 + k3.1) * 2.0 + k4.1) * (time_delta / 6.0));
     }
 }
 
 pub struct MouseMovementModel {
-    mouse_position: Vector2,
+    mouse_position: Vec2,
 }
 
 impl MovementModel for MouseMovementModel {
@@ -42,7 +86,7 @@ impl MovementModel for MouseMovementModel {
         let delta_coord = self.mouse_position - object.get_coordinate();
         let mut speed = object.get_speed();
         speed += delta_coord * 2.0;
-        object.update_coordinate(Vector2 {
+        object.update_coordinate(Vec2 {
             x: speed.x * time_delta,
             y: speed.y * time_delta,
         });
@@ -52,10 +96,10 @@ impl MovementModel for MouseMovementModel {
 impl MouseMovementModel {
     pub fn new() -> Self {
         MouseMovementModel {
-            mouse_position: Vector2 { x: 0.0, y: 0.0 },
+            mouse_position: Vec2 { x: 0.0, y: 0.0 },
         }
     }
-    pub fn set_mouse_position(&mut self, coords: Vector2) {
+    pub fn set_mouse_position(&mut self, coords: Vec2) {
         self.mouse_position = coords;
     }
 }
@@ -64,92 +108,332 @@ impl CollisionModel for BaseCollisionModel {
     fn process_collision(
         &mut self,
         object: &mut Box<impl MovingObject>,
-        pen_vectors: &Vec<Option<Vector2>>,
+        contacts: &[Contact],
         time_delta: f32,
     ) {
-        for pen in pen_vectors.iter() {
-            if let Some(val) = pen {
-                let nrm = val.normalized();
-                let speed = object.get_speed();
-                let coords = object.get_coordinate();
-                let line = Vector2 { x: nrm.y, y: nrm.x };
-
-                object.set_speed(Vector2 {
-                    x: (speed.x * line.x + speed.y * line.y) * line.x
-                        - (speed.x * nrm.x + speed.y * nrm.y) * nrm.x,
-                    y: (speed.x * line.x + speed.y * line.y) * line.y
-                        - (speed.x * nrm.x + speed.y * nrm.y) * nrm.y,
-                });
-                object.set_coordinate(Vector2 {
-                    x: coords.x + val.x,
-                    y: coords.y + val.y,
-                });
-            }
+        for contact in contacts.iter() {
+            let body = object.get_body();
+            let impulse =
+                contact_impulse(&body, object.get_speed(), contact.normal, body.inv_mass());
+            object.update_speed(impulse * body.inv_mass());
+            object.update_coordinate(contact.penetration);
         }
     }
+
+    fn process_pair(
+        &mut self,
+        object: &mut Box<impl MovingObject>,
+        other: &mut Box<impl MovingObject>,
+        contact: &Contact,
+        time_delta: f32,
+    ) {
+        let (body, other_body) = (object.get_body(), other.get_body());
+        let (inv_mass, other_inv_mass) = (body.inv_mass(), other_body.inv_mass());
+        let inv_mass_sum = inv_mass + other_inv_mass;
+        if inv_mass_sum <= 0.0 {
+            return;
+        }
+        let pair = BodyProperties {
+            restitution: body.restitution.min(other_body.restitution),
+            static_friction: (body.static_friction * other_body.static_friction).sqrt(),
+            dynamic_friction: (body.dynamic_friction * other_body.dynamic_friction).sqrt(),
+            ..body
+        };
+        let relative_speed = object.get_speed() - other.get_speed();
+        let impulse = contact_impulse(&pair, relative_speed, contact.normal, inv_mass_sum);
+        object.update_speed(impulse * inv_mass);
+        other.update_speed(impulse * -other_inv_mass);
+        object.update_coordinate(contact.penetration * (inv_mass / inv_mass_sum));
+        other.update_coordinate(contact.penetration * (-other_inv_mass / inv_mass_sum));
+    }
+}
+
+// The normal points out of the obstacle, towards the body being resolved.
+fn contact_impulse(
+    body: &BodyProperties,
+    relative_speed: Vec2,
+    normal: Vec2,
+    inv_mass_sum: f32,
+) -> Vec2 {
+    let normal_speed = relative_speed.dot(normal);
+    if !normal_speed.is_finite() || normal_speed >= 0.0 || inv_mass_sum <= 0.0 {
+        return Vec2::zero();
+    }
+    let normal_impulse = -(1.0 + body.restitution) * normal_speed / inv_mass_sum;
+    let tangent_speed = relative_speed - normal * normal_speed;
+    let sliding = tangent_speed.length();
+    if sliding <= f32::EPSILON {
+        return normal * normal_impulse;
+    }
+    let stop_impulse = sliding / inv_mass_sum;
+    let friction_impulse = if stop_impulse <= body.static_friction * normal_impulse {
+        stop_impulse
+    } else {
+        stop_impulse.min(body.dynamic_friction * normal_impulse)
+    };
+    normal * normal_impulse - tangent_speed * (friction_impulse / sliding)
+}
+
+pub trait Clock {
+    fn now(&mut self) -> f64;
+}
+
+pub struct RealClock {
+    start: Instant,
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        RealClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl RealClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
 }
 
-pub struct PhysicsModel<T: MovementModel, E: CollisionModel> {
+impl Clock for RealClock {
+    fn now(&mut self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+}
+
+#[derive(Default)]
+pub struct ManualClock {
+    time: f64,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn advance(&mut self, time_delta: f64) {
+        self.time += time_delta;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&mut self) -> f64 {
+        self.time
+    }
+}
+
+// Stands in for a moving object inside the body broadphase.
+pub struct BodyProxy {
+    index: usize,
+    u_box: QuadBox,
+}
+
+impl Bounded for BodyProxy {
+    fn get_box(&self) -> QuadBox {
+        self.u_box.clone()
+    }
+}
+
+// Shortest accepted step, a zero or negative period would never drain the accumulator.
+const MIN_PERIOD: f64 = 1e-6;
+
+pub struct PhysicsModel<
+    T: MovementModel,
+    E: CollisionModel,
+    B,
+    C: Clock = RealClock,
+    D = SweepAndPrune<BodyProxy>,
+> {
     m_model: T,
     c_model: E,
+    world: B,
+    clock: C,
+    bodies: D,
+    body_ids: Vec<ObjectId>,
     screen_width: f32,
     screen_height: f32,
     last_time: f64,
+    accumulator: f64,
     period: f64,
+    max_substeps: u32,
 }
 
-impl<T: MovementModel, E: CollisionModel> PhysicsModel<T, E> {
-    pub fn new(m_model: T, c_model: E, width: f32, height: f32, period: f64) -> Self {
+impl<T: MovementModel, E: CollisionModel, B> PhysicsModel<T, E, B> {
+    pub fn new(m_model: T, c_model: E, world: B, width: f32, height: f32, period: f64) -> Self {
+        Self::with_clock(
+            m_model,
+            c_model,
+            world,
+            width,
+            height,
+            period,
+            RealClock::new(),
+        )
+    }
+}
+
+impl<T: MovementModel, E: CollisionModel, B, C: Clock> PhysicsModel<T, E, B, C> {
+    pub fn with_clock(
+        m_model: T,
+        c_model: E,
+        world: B,
+        width: f32,
+        height: f32,
+        period: f64,
+        mut clock: C,
+    ) -> Self {
         PhysicsModel {
-            m_model: m_model,
-            c_model: c_model,
+            m_model,
+            c_model,
+            world,
             screen_height: height,
             screen_width: width,
-            last_time: Self::get_time_s(),
-            period: period,
+            last_time: clock.now(),
+            clock,
+            bodies: SweepAndPrune::new(),
+            body_ids: Vec::new(),
+            accumulator: 0.0,
+            period: period.max(MIN_PERIOD),
+            max_substeps: 8,
+        }
+    }
+}
+
+impl<T: MovementModel, E: CollisionModel, B, C: Clock, D: Broadphase<BodyProxy>>
+    PhysicsModel<T, E, B, C, D>
+{
+    // Bodies default to sweep-and-prune, moving objects barely change order
+    // between steps, which keeps its updates cheap.
+    pub fn with_bodies<F: Broadphase<BodyProxy>>(self, bodies: F) -> PhysicsModel<T, E, B, C, F> {
+        PhysicsModel {
+            m_model: self.m_model,
+            c_model: self.c_model,
+            world: self.world,
+            clock: self.clock,
+            bodies,
+            body_ids: Vec::new(),
+            screen_width: self.screen_width,
+            screen_height: self.screen_height,
+            last_time: self.last_time,
+            accumulator: self.accumulator,
+            period: self.period,
+            max_substeps: self.max_substeps,
         }
     }
 
-    fn get_time_s() -> f64 {
-        let duration_since_epoch = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap();
-        duration_since_epoch.as_millis() as f64 / 1000.0
+    pub fn set_max_substeps(&mut self, max_substeps: u32) {
+        self.max_substeps = max_substeps.max(1);
     }
 
-    fn process(
+    fn process<O: Bounded>(
         &mut self,
-        mov_objects: &mut Vec<Box<impl TreeObject + MovingObject>>,
-        obj_tree: &mut quadtree::QuadTree,
+        mov_objects: &mut [Box<impl Bounded + MovingObject>],
         time_delta: f32,
-    ) {
+    ) where
+        B: Broadphase<O>,
+    {
+        self.process_bodies(mov_objects, time_delta);
         for obj in mov_objects.iter_mut() {
-            let mut pen_vector = obj_tree.query(obj);
-            pen_vector.push(self.screen_collision(obj));
-            self.c_model.process_collision(obj, &pen_vector, time_delta);
+            let mut contacts = self.world.query(obj);
+            if let Some(contact) = self.screen_collision(obj) {
+                contacts.push(contact);
+            }
+            self.c_model.process_collision(obj, &contacts, time_delta);
             self.m_model.process_movement(obj, time_delta);
         }
     }
 
-    pub fn run(
+    fn sync_bodies(&mut self, mov_objects: &[Box<impl Bounded + MovingObject>]) {
+        if self.body_ids.len() != mov_objects.len() {
+            for id in self.body_ids.drain(..) {
+                self.bodies.remove(id);
+            }
+            self.body_ids = mov_objects
+                .iter()
+                .enumerate()
+                .map(|(index, x)| {
+                    self.bodies.insert(BodyProxy {
+                        index,
+                        u_box: x.get_box(),
+                    })
+                })
+                .collect();
+            return;
+        }
+        for (id, x) in self.body_ids.iter().zip(mov_objects.iter()) {
+            self.bodies.update(*id, x.get_box());
+        }
+    }
+
+    fn process_bodies(
         &mut self,
-        mov_objects: &mut Vec<Box<impl MovingObject + TreeObject>>,
-        obj_tree: &mut quadtree::QuadTree,
+        mov_objects: &mut [Box<impl Bounded + MovingObject>],
+        time_delta: f32,
     ) {
-        let curr_time = Self::get_time_s();
-        let time_delta = curr_time - self.last_time;
-        if time_delta >= self.period {
-            self.last_time = curr_time;
-            self.process(mov_objects, obj_tree, time_delta as f32);
+        self.sync_bodies(mov_objects);
+        for (a, b) in self.bodies.pairs() {
+            let (Some(a), Some(b)) = (self.bodies.get(a), self.bodies.get(b)) else {
+                continue;
+            };
+            let (first, second) = (a.index.min(b.index), a.index.max(b.index));
+            let (head, tail) = mov_objects.split_at_mut(second);
+            let (object, other) = (&mut head[first], &mut tail[0]);
+            // Earlier pairs in this step may already have pushed the two apart.
+            let (object_box, other_box) = (object.get_box(), other.get_box());
+            let pen = penetration(
+                &object_box,
+                object.get_shape(),
+                &other_box,
+                other.get_shape(),
+            );
+            if let Some(pen) = pen {
+                let contact = Contact::new(None, other_box, pen);
+                self.c_model
+                    .process_pair(object, other, &contact, time_delta);
+            }
         }
     }
 
+    pub fn step<O: Bounded>(
+        &mut self,
+        mov_objects: &mut [Box<impl MovingObject + Bounded>],
+        time_delta: f32,
+    ) where
+        B: Broadphase<O>,
+    {
+        self.process(mov_objects, time_delta);
+    }
+
+    pub fn run<O: Bounded>(&mut self, mov_objects: &mut [Box<impl MovingObject + Bounded>]) -> u32
+    where
+        B: Broadphase<O>,
+    {
+        let curr_time = self.clock.now();
+        self.accumulator += curr_time - self.last_time;
+        self.last_time = curr_time;
+        let mut steps = 0;
+        while self.accumulator >= self.period && steps < self.max_substeps {
+            self.process(mov_objects, self.period as f32);
+            self.accumulator -= self.period;
+            steps += 1;
+        }
+        // After a stall drop the backlog instead of trying to catch up with it next frame.
+        if steps == self.max_substeps {
+            self.accumulator = self.accumulator.min(self.period);
+        }
+        steps
+    }
+
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.period).clamp(0.0, 1.0) as f32
+    }
+
     fn screen_collision(
         &mut self,
-        object: &mut Box<impl MovingObject + TreeObject>,
-    ) -> Option<Vector2> {
+        object: &mut Box<impl MovingObject + Bounded>,
+    ) -> Option<Contact> {
         let bx = object.get_box();
-        let mut ret = Vector2 { x: 0.0, y: 0.0 };
+        let mut ret = Vec2 { x: 0.0, y: 0.0 };
         if bx.get_lefttop().x <= 0.0 {
             ret.x = -bx.get_lefttop().x;
         }
@@ -163,7 +447,8 @@ impl<T: MovementModel, E: CollisionModel> PhysicsModel<T, E> {
             ret.y = self.screen_height - bx.get_bottom_y();
         }
         if ret.x != 0.0 || ret.y != 0.0 {
-            return Some(ret);
+            let screen = QuadBox::new(0.0, 0.0, self.screen_width, self.screen_height);
+            return Some(Contact::new(None, screen, ret));
         }
         None
     }
@@ -175,4 +460,12 @@ impl<T: MovementModel, E: CollisionModel> PhysicsModel<T, E> {
     pub fn get_c_model(&mut self) -> &mut E {
         &mut self.c_model
     }
+
+    pub fn get_world(&mut self) -> &mut B {
+        &mut self.world
+    }
+
+    pub fn get_clock(&mut self) -> &mut C {
+        &mut self.clock
+    }
 }
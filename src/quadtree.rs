@@ -1,25 +1,380 @@
+use crate::broadphase::Broadphase;
+use crate::math::Vec2;
+use crate::narrowphase::{penetration, Shape};
+use crate::slab::Slab;
+#[cfg(feature = "serde")]
+use crate::slab::Slot;
 use core::f32;
-use dyn_clone::DynClone;
-use raylib::{
-    math::Rectangle,
-    prelude::{RaylibDrawHandle, Vector2},
-};
-use std::collections::VecDeque;
-
-pub trait TreeObject: std::fmt::Display + DynClone {
+#[cfg(feature = "serde")]
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::ops::ControlFlow;
+
+pub trait Bounded {
     fn get_box(&self) -> QuadBox;
-    fn draw(&self, draw_handler: &mut RaylibDrawHandle);
+    fn get_shape(&self) -> Shape {
+        Shape::Aabb
+    }
+}
+
+impl<T: Bounded + ?Sized> Bounded for Box<T> {
+    fn get_box(&self) -> QuadBox {
+        (**self).get_box()
+    }
+    fn get_shape(&self) -> Shape {
+        (**self).get_shape()
+    }
+}
+
+impl Bounded for QuadBox {
+    fn get_box(&self) -> QuadBox {
+        self.clone()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ObjectId {
+    pub(crate) index: u32,
+    pub(crate) generation: u32,
 }
-pub struct QuadTree {
-    root: Subtree,
+
+pub struct QuadTree<T> {
+    nodes: Vec<Node>,
+    free_nodes: Vec<u32>,
+    slab: Slab<T>,
     u_box: QuadBox,
+    policy: Box<dyn SplitPolicy + Send + Sync>,
+    looseness: f32,
+}
+
+const ROOT: usize = 0;
+const NO_CHILDREN: u32 = u32::MAX;
+#[cfg(feature = "parallel")]
+const PARALLEL_BUILD_MIN: usize = 4096;
+
+#[derive(Clone, Debug)]
+pub struct RayHit {
+    pub id: ObjectId,
+    pub point: Vec2,
+    pub normal: Vec2,
+    pub distance: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Neighbour {
+    pub id: ObjectId,
+    pub distance: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct DebugCell {
+    pub u_box: QuadBox,
+    pub depth: u32,
+    pub num_of_elems: usize,
+    pub visited: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TreeStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_depth: u32,
+    pub elems_per_depth: Vec<usize>,
+    pub root_straddlers: usize,
+    pub avg_elems_per_leaf: f32,
+}
+
+pub trait SplitPolicy: std::fmt::Debug + Any {
+    fn should_split(&self, node_box: &QuadBox, depth: u32, num_of_elems: usize) -> bool;
+    fn split_point(&self, node_box: &QuadBox, _elems: &mut dyn Iterator<Item = &QuadBox>) -> Vec2 {
+        node_box.get_center()
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CountPolicy {
+    pub max_depth: u32,
+    pub max_num_of_elems: usize,
+}
+
+impl Default for CountPolicy {
+    fn default() -> Self {
+        Self {
+            max_depth: 128,
+            max_num_of_elems: 4,
+        }
+    }
+}
+
+impl SplitPolicy for CountPolicy {
+    fn should_split(&self, _node_box: &QuadBox, depth: u32, num_of_elems: usize) -> bool {
+        depth < self.max_depth && num_of_elems > self.max_num_of_elems
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MinCellSizePolicy {
+    min_cell_size: f32,
     max_depth: u32,
     max_num_of_elems: usize,
 }
 
-struct Subtree(Option<Box<Node>>);
+impl Default for MinCellSizePolicy {
+    fn default() -> Self {
+        Self {
+            min_cell_size: 1.0,
+            max_depth: 128,
+            max_num_of_elems: 4,
+        }
+    }
+}
+
+impl MinCellSizePolicy {
+    pub fn new(min_cell_size: f32, max_num_of_elems: usize) -> Option<Self> {
+        if !min_cell_size.is_finite() || min_cell_size <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            min_cell_size,
+            max_num_of_elems,
+            ..Self::default()
+        })
+    }
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+}
 
-#[derive(Clone)]
+impl SplitPolicy for MinCellSizePolicy {
+    fn should_split(&self, node_box: &QuadBox, depth: u32, num_of_elems: usize) -> bool {
+        depth < self.max_depth
+            && num_of_elems > self.max_num_of_elems
+            && node_box.width / 2.0 >= self.min_cell_size
+            && node_box.height / 2.0 >= self.min_cell_size
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MedianPolicy {
+    pub max_depth: u32,
+    pub max_num_of_elems: usize,
+}
+
+impl Default for MedianPolicy {
+    fn default() -> Self {
+        Self {
+            max_depth: 128,
+            max_num_of_elems: 4,
+        }
+    }
+}
+
+impl SplitPolicy for MedianPolicy {
+    fn should_split(&self, _node_box: &QuadBox, depth: u32, num_of_elems: usize) -> bool {
+        depth < self.max_depth && num_of_elems > self.max_num_of_elems
+    }
+    fn split_point(&self, node_box: &QuadBox, elems: &mut dyn Iterator<Item = &QuadBox>) -> Vec2 {
+        let (mut xs, mut ys): (Vec<f32>, Vec<f32>) = elems
+            .map(|x| {
+                let center = x.get_center();
+                (center.x, center.y)
+            })
+            .unzip();
+        let mut ret = node_box.get_center();
+        if xs.is_empty() {
+            return ret;
+        }
+        let mid = xs.len() / 2;
+        let x = *xs.select_nth_unstable_by(mid, f32::total_cmp).1;
+        let y = *ys.select_nth_unstable_by(mid, f32::total_cmp).1;
+        if x > node_box.x && x < node_box.get_right_x() {
+            ret.x = x;
+        }
+        if y > node_box.y && y < node_box.get_bottom_y() {
+            ret.y = y;
+        }
+        ret
+    }
+}
+
+pub struct QuadTreeBuilder {
+    u_box: QuadBox,
+    looseness: f32,
+    policy: Box<dyn SplitPolicy + Send + Sync>,
+}
+
+impl Default for QuadTreeBuilder {
+    fn default() -> Self {
+        QuadTreeBuilder {
+            u_box: QuadBox::new(0.0, 0.0, 0.0, 0.0),
+            looseness: 1.0,
+            policy: Box::new(CountPolicy::default()),
+        }
+    }
+}
+
+impl QuadTreeBuilder {
+    pub fn new() -> QuadTreeBuilder {
+        Self::default()
+    }
+    pub fn coordinate(mut self, x: f32, y: f32) -> QuadTreeBuilder {
+        self.u_box.x = x;
+        self.u_box.y = y;
+        self
+    }
+    pub fn size(mut self, width: f32, height: f32) -> QuadTreeBuilder {
+        self.u_box.width = width;
+        self.u_box.height = height;
+        self
+    }
+    /// `looseness` above 1.0 enlarges every child cell by that factor, so
+    /// elements sink to the deepest cell holding their centre instead of
+    /// piling up in the parent when they cross a cell boundary.
+    pub fn looseness(mut self, looseness: f32) -> QuadTreeBuilder {
+        self.looseness = looseness;
+        self
+    }
+    pub fn policy(mut self, policy: impl SplitPolicy + Send + Sync + 'static) -> QuadTreeBuilder {
+        self.policy = Box::new(policy);
+        self
+    }
+
+    pub fn build<T: Bounded>(self) -> QuadTree<T> {
+        let mut u_box = self.u_box.clone();
+        if u_box.width <= 0.0 || u_box.height <= 0.0 {
+            u_box.width = u_box.width.max(1.0);
+            u_box.height = u_box.height.max(1.0);
+        }
+        QuadTree {
+            nodes: vec![Node::new()],
+            free_nodes: Vec::new(),
+            slab: Slab::new(),
+            u_box,
+            policy: self.policy,
+            looseness: self.looseness,
+        }
+    }
+
+    pub fn build_from_slice<T: Bounded + Clone>(mut self, items: &[T]) -> QuadTree<T> {
+        let mut bounds: Option<QuadBox> = None;
+        if self.u_box.width > 0.0 && self.u_box.height > 0.0 {
+            bounds = Some(self.u_box.clone());
+        }
+        let mut entries: Vec<Entry> = Vec::with_capacity(items.len());
+        let mut slab: Slab<T> = Slab::new();
+        for elem in items.iter() {
+            let u_box = elem.get_box();
+            if u_box.is_finite() {
+                bounds = Some(match bounds {
+                    Some(x) => x.union(&u_box),
+                    None => u_box.clone(),
+                });
+            }
+            entries.push(Entry {
+                id: slab.insert(u_box.clone(), elem.clone()),
+                u_box,
+            });
+        }
+        if let Some(x) = bounds {
+            self.u_box = x;
+        }
+
+        let mut tree = self.build();
+        let params = BuildParams {
+            policy: tree.policy.as_ref(),
+            looseness: tree.looseness,
+        };
+        tree.nodes = Node::build(entries, &tree.u_box, 0, params);
+        tree.slab = slab;
+        tree
+    }
+}
+
+enum Candidate {
+    Cell(usize, QuadBox),
+    Elem(ObjectId),
+}
+
+struct HeapItem {
+    distance: f32,
+    candidate: Candidate,
+}
+
+impl PartialEq for HeapItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapItem {}
+
+impl PartialOrd for HeapItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+/// Result of a box query. `penetration` is the translation that pushes the
+/// query box out of `other_box`; `id` is `None` for contacts that do not come
+/// from a tree element (e.g. screen edges).
+#[derive(Clone, Debug)]
+pub struct Contact {
+    pub id: Option<ObjectId>,
+    pub other_box: QuadBox,
+    pub penetration: Vec2,
+    pub normal: Vec2,
+    pub depth: f32,
+}
+
+impl Contact {
+    pub fn new(id: Option<ObjectId>, other_box: QuadBox, penetration: Vec2) -> Self {
+        Contact {
+            id,
+            other_box,
+            penetration,
+            normal: penetration.normalized(),
+            depth: penetration.length(),
+        }
+    }
+}
+
+fn json_num(x: f32) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        String::from("null")
+    }
+}
+
+fn json_string(x: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in x.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            c if c.is_control() => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct QuadBox {
     x: f32,
     y: f32,
@@ -47,15 +402,15 @@ impl QuadBox {
         }
     }
 
-    pub fn get_center(&self) -> Vector2 {
-        Vector2 {
+    pub fn get_center(&self) -> Vec2 {
+        Vec2 {
             x: self.x + self.width / 2.0,
             y: self.y + self.height / 2.0,
         }
     }
 
-    pub fn get_lefttop(&self) -> Vector2 {
-        Vector2 {
+    pub fn get_lefttop(&self) -> Vec2 {
+        Vec2 {
             x: self.x,
             y: self.y,
         }
@@ -77,13 +432,34 @@ impl QuadBox {
         self.height = self.y - y;
     }
 
-    pub fn get_size(&self) -> Vector2 {
-        Vector2 {
+    pub fn get_size(&self) -> Vec2 {
+        Vec2 {
             x: self.width,
             y: self.height,
         }
     }
 
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"x\":{},\"y\":{},\"width\":{},\"height\":{}}}",
+            json_num(self.x),
+            json_num(self.y),
+            json_num(self.width),
+            json_num(self.height)
+        )
+    }
+
+    fn union(&self, u_box: &QuadBox) -> QuadBox {
+        let x = self.x.min(u_box.x);
+        let y = self.y.min(u_box.y);
+        QuadBox::new(
+            x,
+            y,
+            self.get_right_x().max(u_box.get_right_x()) - x,
+            self.get_bottom_y().max(u_box.get_bottom_y()) - y,
+        )
+    }
+
     fn contains(&self, u_box: &QuadBox) -> bool {
         u_box.x >= self.x
             && u_box.get_right_x() <= self.get_right_x()
@@ -91,13 +467,30 @@ impl QuadBox {
             && u_box.get_bottom_y() <= self.get_bottom_y()
     }
 
+    pub fn expanded(&self, factor: f32) -> QuadBox {
+        let pad = self.get_size() * ((factor - 1.0) / 2.0);
+        QuadBox::new(
+            self.x - pad.x,
+            self.y - pad.y,
+            self.width + pad.x * 2.0,
+            self.height + pad.y * 2.0,
+        )
+    }
+
+    pub fn contains_point(&self, point: Vec2) -> bool {
+        point.x >= self.x
+            && point.x <= self.get_right_x()
+            && point.y >= self.y
+            && point.y <= self.get_bottom_y()
+    }
+
     pub fn intersects(&self, u_box: &QuadBox) -> bool {
         let ret = self.minkowski_difference(&u_box);
         ret.x <= 0.0 && ret.y <= 0.0 && (ret.x + ret.width) >= 0.0 && (ret.y + ret.height) >= 0.0
     }
 
-    pub fn minkowski_difference(&self, u_box: &QuadBox) -> Rectangle {
-        let mut ret = Rectangle {
+    pub fn minkowski_difference(&self, u_box: &QuadBox) -> QuadBox {
+        let mut ret = QuadBox {
             y: self.y - u_box.get_bottom_y(),
             x: self.x - u_box.get_right_x(),
             height: 0.0,
@@ -108,8 +501,8 @@ impl QuadBox {
         ret
     }
 
-    pub fn pen_vector(&self, u_box: &QuadBox, md: &Rectangle) -> Option<Vector2> {
-        let mut vec = Vector2 { x: 0.0, y: 0.0 };
+    pub fn pen_vector(&self, u_box: &QuadBox, md: &QuadBox) -> Option<Vec2> {
+        let mut vec = Vec2 { x: 0.0, y: 0.0 };
         let mut min = f32::MAX;
         if md.x.abs() < min {
             min = md.x.abs();
@@ -137,280 +530,1327 @@ impl QuadBox {
             return None;
         }
     }
+
+    pub(crate) fn is_finite(&self) -> bool {
+        [self.x, self.y, self.width, self.height]
+            .iter()
+            .all(|x| x.is_finite())
+    }
+
+    pub(crate) fn overlaps(&self, u_box: &QuadBox) -> bool {
+        self.intersects(u_box)
+            && self
+                .pen_vector(u_box, &self.minkowski_difference(u_box))
+                .is_some()
+    }
+
+    pub fn distance_to_point(&self, point: Vec2) -> f32 {
+        let dx = (self.x - point.x)
+            .max(point.x - self.get_right_x())
+            .max(0.0);
+        let dy = (self.y - point.y)
+            .max(point.y - self.get_bottom_y())
+            .max(0.0);
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    pub fn ray_intersection(&self, origin: Vec2, dir: Vec2, max_dist: f32) -> Option<(f32, Vec2)> {
+        let mut t_min = 0.0;
+        let mut t_max = max_dist;
+        let mut normal = Vec2 { x: 0.0, y: 0.0 };
+        let axes = [
+            (
+                origin.x,
+                dir.x,
+                self.x,
+                self.get_right_x(),
+                Vec2 { x: 1.0, y: 0.0 },
+            ),
+            (
+                origin.y,
+                dir.y,
+                self.y,
+                self.get_bottom_y(),
+                Vec2 { x: 0.0, y: 1.0 },
+            ),
+        ];
+        for (o, d, low, high, axis) in axes {
+            if d == 0.0 {
+                if o < low || o > high {
+                    return None;
+                }
+                continue;
+            }
+            let mut t1 = (low - o) / d;
+            let mut t2 = (high - o) / d;
+            if t1 > t2 {
+                std::mem::swap(&mut t1, &mut t2);
+            }
+            if t1 > t_min {
+                t_min = t1;
+                normal = axis * -d.signum();
+            }
+            if t2 < t_max {
+                t_max = t2;
+            }
+            if t_min > t_max {
+                return None;
+            }
+        }
+        if normal.x == 0.0 && normal.y == 0.0 {
+            normal = -dir;
+        }
+        Some((t_min, normal))
+    }
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Entry {
+    id: ObjectId,
+    u_box: QuadBox,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct Node {
-    values: Vec<Box<dyn TreeObject>>,
-    children: [Subtree; 4],
+    children: u32,
+    split: Vec2,
+    values: Vec<Entry>,
+}
+
+#[derive(Clone, Copy)]
+struct BuildParams<'a> {
+    policy: &'a (dyn SplitPolicy + Send + Sync),
+    looseness: f32,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct TreeRef<'a, T> {
+    u_box: &'a QuadBox,
+    looseness: f32,
+    nodes: &'a [Node],
+    free_nodes: &'a [u32],
+    items: &'a [Slot<T>],
+    free_items: &'a [u32],
+    policy: Option<PolicyRepr>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct TreeRepr<T> {
+    u_box: QuadBox,
+    looseness: f32,
+    nodes: Vec<Node>,
+    free_nodes: Vec<u32>,
+    items: Vec<Slot<T>>,
+    free_items: Vec<u32>,
+    #[serde(default)]
+    policy: Option<PolicyRepr>,
+}
+
+// Only the built-in policies can be written into a snapshot, custom ones
+// have to be handed back through `QuadTree::restore_with_policy`.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+enum PolicyRepr {
+    Count(CountPolicy),
+    MinCellSize(MinCellSizePolicy),
+    Median(MedianPolicy),
+}
+
+#[cfg(feature = "serde")]
+impl PolicyRepr {
+    fn new(policy: &(dyn SplitPolicy + Send + Sync)) -> Option<Self> {
+        let policy: &dyn Any = policy;
+        if let Some(x) = policy.downcast_ref::<CountPolicy>() {
+            Some(PolicyRepr::Count(x.clone()))
+        } else if let Some(x) = policy.downcast_ref::<MinCellSizePolicy>() {
+            Some(PolicyRepr::MinCellSize(x.clone()))
+        } else {
+            policy
+                .downcast_ref::<MedianPolicy>()
+                .map(|x| PolicyRepr::Median(x.clone()))
+        }
+    }
+
+    fn into_policy(self) -> Result<Box<dyn SplitPolicy + Send + Sync>, &'static str> {
+        match self {
+            PolicyRepr::Count(x) => Ok(Box::new(x)),
+            PolicyRepr::MinCellSize(x) => {
+                MinCellSizePolicy::new(x.min_cell_size, x.max_num_of_elems)
+                    .map(|p| {
+                        Box::new(p.max_depth(x.max_depth)) as Box<dyn SplitPolicy + Send + Sync>
+                    })
+                    .ok_or("invalid minimum cell size")
+            }
+            PolicyRepr::Median(x) => Ok(Box::new(x)),
+        }
+    }
 }
 
 impl Node {
     fn new() -> Self {
         Self {
+            children: NO_CHILDREN,
+            split: Vec2 { x: 0.0, y: 0.0 },
             values: Vec::new(),
-            children: [
-                Subtree::new(),
-                Subtree::new(),
-                Subtree::new(),
-                Subtree::new(),
-            ],
         }
     }
-    fn get_vec_of_refs(&self) -> Vec<&Subtree> {
-        let mut vc: Vec<&Subtree> = Vec::new();
-        for n in self.children.iter() {
-            vc.push(n);
+
+    fn is_leaf(&self) -> bool {
+        self.children == NO_CHILDREN
+    }
+
+    fn child(&self, idx: i32) -> usize {
+        self.children as usize + idx as usize
+    }
+
+    fn child_box(&self, node_box: &QuadBox, idx: i32) -> Option<QuadBox> {
+        Self::compute_box(node_box, self.split, idx)
+    }
+
+    fn get_quadrant(center: Vec2, elem_box: &QuadBox) -> i32 {
+        if elem_box.get_right_x() <= center.x {
+            if elem_box.get_bottom_y() <= center.y {
+                return 0;
+            } else if elem_box.y >= center.y {
+                return 2;
+            } else {
+                return -1;
+            }
+        } else if elem_box.x >= center.x {
+            if elem_box.get_bottom_y() <= center.y {
+                return 1;
+            } else if elem_box.y >= center.y {
+                return 3;
+            } else {
+                return -1;
+            }
+        } else {
+            return -1;
         }
-        vc
     }
-}
 
-impl std::fmt::Display for Node {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        if write!(f, "[") == Ok(()) {
-            for n in self.values.iter() {
-                let tmp = write!(f, "{}", n);
-                if tmp != Ok(()) {
-                    return tmp;
-                };
+    fn get_loose_quadrant(
+        node_box: &QuadBox,
+        center: Vec2,
+        elem_box: &QuadBox,
+        looseness: f32,
+    ) -> i32 {
+        let tight = Self::get_quadrant(center, elem_box);
+        match Self::compute_box(node_box, center, tight) {
+            Some(x) if x.contains(elem_box) => return tight,
+            _ => (),
+        }
+        let elem_center = elem_box.get_center();
+        let mut i = 0;
+        if elem_center.x >= center.x {
+            i += 1;
+        }
+        if elem_center.y >= center.y {
+            i += 2;
+        }
+        match Self::compute_box(node_box, center, i) {
+            Some(x) if x.expanded(looseness).contains(elem_box) => i,
+            _ => -1,
+        }
+    }
+
+    fn pick_quadrant(node_box: &QuadBox, center: Vec2, elem_box: &QuadBox, looseness: f32) -> i32 {
+        if looseness > 1.0 {
+            Self::get_loose_quadrant(node_box, center, elem_box, looseness)
+        } else {
+            Self::get_quadrant(center, elem_box)
+        }
+    }
+
+    fn build(
+        entries: Vec<Entry>,
+        node_box: &QuadBox,
+        depth: u32,
+        params: BuildParams,
+    ) -> Vec<Node> {
+        let mut nodes = vec![Node::new()];
+        Self::build_into(&mut nodes, ROOT, entries, node_box, depth, params);
+        nodes
+    }
+
+    fn build_into(
+        nodes: &mut Vec<Node>,
+        node: usize,
+        entries: Vec<Entry>,
+        node_box: &QuadBox,
+        depth: u32,
+        params: BuildParams,
+    ) {
+        if !params.policy.should_split(node_box, depth, entries.len()) {
+            nodes[node].values = entries;
+            return;
+        }
+        let split = params
+            .policy
+            .split_point(node_box, &mut entries.iter().map(|x| &x.u_box));
+        let mut values: Vec<Entry> = Vec::new();
+        let mut buckets: Vec<Vec<Entry>> = vec![Vec::new(); 4];
+        for entry in entries {
+            let i = Self::pick_quadrant(node_box, split, &entry.u_box, params.looseness);
+            if i >= 0 {
+                buckets[i as usize].push(entry);
+            } else {
+                values.push(entry);
             }
         }
-        write!(f, "]")
+        let first = nodes.len();
+        nodes[node].children = first as u32;
+        nodes[node].split = split;
+        nodes[node].values = values;
+        for _ in 0..4 {
+            nodes.push(Node::new());
+        }
+        let boxes: Vec<QuadBox> = (0..4)
+            .filter_map(|i| Self::compute_box(node_box, split, i))
+            .collect();
+        Self::build_children(nodes, first, buckets, &boxes, depth + 1, params);
+    }
+
+    #[cfg(feature = "parallel")]
+    fn build_children(
+        nodes: &mut Vec<Node>,
+        first: usize,
+        buckets: Vec<Vec<Entry>>,
+        boxes: &[QuadBox],
+        depth: u32,
+        params: BuildParams,
+    ) {
+        use rayon::prelude::*;
+        if buckets.iter().map(Vec::len).sum::<usize>() < PARALLEL_BUILD_MIN {
+            for (i, (bucket, bx)) in buckets.into_iter().zip(boxes.iter()).enumerate() {
+                Self::build_into(nodes, first + i, bucket, bx, depth, params);
+            }
+            return;
+        }
+        let subtrees: Vec<Vec<Node>> = buckets
+            .into_par_iter()
+            .zip(boxes.par_iter())
+            .map(|(bucket, bx)| Self::build(bucket, bx, depth, params))
+            .collect();
+        // Every subtree keeps its root in the reserved child slot, the rest
+        // of its nodes are appended with their child indices shifted.
+        for (i, subtree) in subtrees.into_iter().enumerate() {
+            let offset = nodes.len() as u32 - 1;
+            let mut subtree = subtree.into_iter().map(|mut n| {
+                if !n.is_leaf() {
+                    n.children += offset;
+                }
+                n
+            });
+            if let Some(root) = subtree.next() {
+                nodes[first + i] = root;
+            }
+            nodes.extend(subtree);
+        }
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn build_children(
+        nodes: &mut Vec<Node>,
+        first: usize,
+        buckets: Vec<Vec<Entry>>,
+        boxes: &[QuadBox],
+        depth: u32,
+        params: BuildParams,
+    ) {
+        for (i, (bucket, bx)) in buckets.into_iter().zip(boxes.iter()).enumerate() {
+            Self::build_into(nodes, first + i, bucket, bx, depth, params);
+        }
+    }
+
+    fn compute_box(node_box: &QuadBox, split: Vec2, idx: i32) -> Option<QuadBox> {
+        let left = split.x - node_box.x;
+        let top = split.y - node_box.y;
+        let right = node_box.get_right_x() - split.x;
+        let bottom = node_box.get_bottom_y() - split.y;
+        match idx {
+            0 => Some(QuadBox::new(node_box.x, node_box.y, left, top)),
+            1 => Some(QuadBox::new(split.x, node_box.y, right, top)),
+            2 => Some(QuadBox::new(node_box.x, split.y, left, bottom)),
+            3 => Some(QuadBox::new(split.x, split.y, right, bottom)),
+            _ => None,
+        }
     }
 }
 
-impl Subtree {
-    fn new() -> Self {
-        Self(None)
+impl<T> QuadTree<T> {
+    fn object(&self, id: ObjectId) -> &T {
+        match &self.slab.item(id.index).object {
+            Some(x) => x,
+            None => panic!("Entry without object!"),
+        }
+    }
+
+    fn quadrant(&self, node: usize, node_box: &QuadBox, elem_box: &QuadBox) -> i32 {
+        let split = self.nodes[node].split;
+        Node::pick_quadrant(node_box, split, elem_box, self.looseness)
     }
 
-    fn draw_tree(&mut self, draw_handler: &mut RaylibDrawHandle) {
-        match &mut self.0 {
-            Some(x) => {
-                for val in x.values.iter_mut() {
-                    val.draw(draw_handler);
+    fn route(&self, elem_box: &QuadBox) -> usize {
+        let mut node = ROOT;
+        let mut node_box = self.u_box.clone();
+        while !self.nodes[node].is_leaf() {
+            let i = self.quadrant(node, &node_box, elem_box);
+            match self.nodes[node].child_box(&node_box, i) {
+                Some(bx) => {
+                    node = self.nodes[node].child(i);
+                    node_box = bx;
                 }
-                for (idx, elem) in x.children.iter_mut().enumerate() {
-                    elem.draw_tree(draw_handler);
+                None => break,
+            }
+        }
+        node
+    }
+
+    fn search_box(&self, cell: &QuadBox) -> QuadBox {
+        if self.looseness > 1.0 {
+            cell.expanded(self.looseness)
+        } else {
+            cell.clone()
+        }
+    }
+
+    fn grow_to_fit(&mut self, elem_box: &QuadBox) {
+        while elem_box.is_finite()
+            && !self.u_box.contains(elem_box)
+            && self.u_box.width > 0.0
+            && self.u_box.height > 0.0
+            && self.u_box.get_size().length().is_finite()
+        {
+            self.grow(elem_box);
+        }
+    }
+
+    fn grow(&mut self, elem_box: &QuadBox) {
+        let old_box = self.u_box.clone();
+        let mut new_box = QuadBox::new(
+            old_box.x,
+            old_box.y,
+            old_box.width * 2.0,
+            old_box.height * 2.0,
+        );
+        let mut split = Vec2 {
+            x: old_box.get_right_x(),
+            y: old_box.get_bottom_y(),
+        };
+        let mut i = 0;
+        if elem_box.x < old_box.x {
+            new_box.x -= old_box.width;
+            split.x = old_box.x;
+            i += 1;
+        }
+        if elem_box.y < old_box.y {
+            new_box.y -= old_box.height;
+            split.y = old_box.y;
+            i += 2;
+        }
+        self.u_box = new_box;
+        // The old root can only be reused as a child if every entry under it
+        // routes back into it, edge-touching boxes may go to a neighbour instead.
+        let routed = self
+            .nodes
+            .iter()
+            .flat_map(|x| x.values.iter())
+            .all(|x| Node::pick_quadrant(&self.u_box, split, &x.u_box, self.looseness) == i);
+        if !routed || Node::compute_box(&self.u_box, split, i).as_ref() != Some(&old_box) {
+            self.rebuild();
+            return;
+        }
+        let first = self.alloc_children();
+        self.nodes.swap(ROOT, first as usize + i as usize);
+        self.nodes[ROOT].children = first;
+        self.nodes[ROOT].split = split;
+    }
+
+    fn rebuild(&mut self) {
+        let nodes = std::mem::replace(&mut self.nodes, vec![Node::new()]);
+        self.free_nodes.clear();
+        let root_box = self.u_box.clone();
+        for node in nodes {
+            for entry in node.values {
+                self.add_entry(ROOT, 0, &root_box, entry);
+            }
+        }
+    }
+
+    fn alloc_children(&mut self) -> u32 {
+        match self.free_nodes.pop() {
+            Some(x) => x,
+            None => {
+                let first = self.nodes.len() as u32;
+                for _ in 0..4 {
+                    self.nodes.push(Node::new());
                 }
+                first
             }
-            None => {}
         }
     }
 
-    fn get_boxes(&mut self, init_box: &QuadBox, boxes: &mut Vec<Option<QuadBox>>) {
-        match &mut self.0 {
-            Some(x) => {
-                boxes.push(Some(init_box.clone()));
-                for (idx, elem) in x.children.iter_mut().enumerate() {
-                    let bx_1 = Self::compute_box(init_box, idx as i32);
-                    elem.get_boxes(&bx_1.as_ref().unwrap(), boxes);
+    fn add_entry(&mut self, node: usize, depth: u32, node_box: &QuadBox, entry: Entry) {
+        let mut node = node;
+        let mut depth = depth;
+        let mut node_box = node_box.clone();
+        loop {
+            if self.nodes[node].is_leaf() {
+                let count = self.nodes[node].values.len() + 1;
+                if !self.policy.should_split(&node_box, depth, count) {
+                    self.nodes[node].values.push(entry);
+                    return;
+                }
+                self.split(node, &node_box);
+                continue;
+            }
+            let i = self.quadrant(node, &node_box, &entry.u_box);
+            match self.nodes[node].child_box(&node_box, i) {
+                Some(bx) => {
+                    node = self.nodes[node].child(i);
+                    node_box = bx;
+                    depth += 1;
+                }
+                None => {
+                    self.nodes[node].values.push(entry);
+                    return;
                 }
             }
-            None => {}
         }
     }
 
-    fn print_tree(&mut self) {
-        let mut queue: VecDeque<Vec<&Subtree>> = VecDeque::new();
-        let mut level: u32 = 0;
-        match &self.0 {
-            Some(x) => {
-                let vc = x.get_vec_of_refs();
-                queue.push_back(vc);
+    fn split(&mut self, node: usize, node_box: &QuadBox) {
+        let split = self.policy.split_point(
+            node_box,
+            &mut self.nodes[node].values.iter().map(|x| &x.u_box),
+        );
+        let first = self.alloc_children();
+        self.nodes[node].children = first;
+        self.nodes[node].split = split;
+
+        let values = std::mem::take(&mut self.nodes[node].values);
+        for entry in values {
+            let i = self.quadrant(node, node_box, &entry.u_box);
+            if i >= 0 {
+                let child = self.nodes[node].child(i);
+                self.nodes[child].values.push(entry);
+            } else {
+                self.nodes[node].values.push(entry);
             }
-            None => {}
         }
-        println!("root: {}", self.0.as_ref().unwrap());
+    }
 
-        while !queue.is_empty() {
-            let mut vc: Vec<&Subtree> = Vec::new();
-            let tmp = queue.pop_front().unwrap();
-            print!("{} level_{}: ", tmp.len(), level);
+    fn remove_entry(
+        &mut self,
+        node: usize,
+        depth: u32,
+        node_box: &QuadBox,
+        elem_box: &QuadBox,
+        id: ObjectId,
+    ) -> Option<Entry> {
+        let i = if self.nodes[node].is_leaf() {
+            -1
+        } else {
+            self.quadrant(node, node_box, elem_box)
+        };
+        let ret = if i >= 0 {
+            let child_box = self.nodes[node].child_box(node_box, i)?;
+            let child = self.nodes[node].child(i);
+            self.remove_entry(child, depth + 1, &child_box, elem_box, id)
+        } else {
+            let values = &mut self.nodes[node].values;
+            let pos = values.iter().position(|x| x.id == id)?;
+            Some(values.swap_remove(pos))
+        };
+        if ret.is_some() {
+            self.merge(node, depth, node_box);
+        }
+        ret
+    }
 
-            for n in tmp.iter() {
-                match &n.0 {
-                    Some(x) => {
-                        print!("{}", x);
-                        vc.append(&mut x.get_vec_of_refs());
-                    }
-                    None => {}
+    fn merge(&mut self, node: usize, depth: u32, node_box: &QuadBox) -> bool {
+        if self.nodes[node].is_leaf() {
+            return false;
+        }
+        let first = self.nodes[node].children as usize;
+        let mut total = 0;
+        for n in self.nodes[first..first + 4].iter() {
+            if !n.is_leaf() {
+                return false;
+            }
+            total += n.values.len();
+        }
+        if self.policy.should_split(node_box, depth, total) {
+            return false;
+        }
+        for child in first..first + 4 {
+            let mut values = std::mem::take(&mut self.nodes[child].values);
+            self.nodes[node].values.append(&mut values);
+        }
+        self.nodes[node].children = NO_CHILDREN;
+        self.free_nodes.push(first as u32);
+        true
+    }
+
+    fn compact_node(&mut self, node: usize, depth: u32, node_box: &QuadBox) {
+        if !self.nodes[node].is_leaf() {
+            for i in 0..4 {
+                if let Some(bx) = self.nodes[node].child_box(node_box, i) {
+                    let child = self.nodes[node].child(i);
+                    self.compact_node(child, depth + 1, &bx);
                 }
             }
-            if !vc.is_empty() {
-                queue.push_back(vc);
+            self.merge(node, depth, node_box);
+        }
+    }
+
+    fn update_entry(
+        &mut self,
+        node: usize,
+        depth: u32,
+        node_box: &QuadBox,
+        id: ObjectId,
+        old_box: &QuadBox,
+        new_box: &QuadBox,
+    ) -> bool {
+        let (old_i, new_i) = if self.nodes[node].is_leaf() {
+            (-1, -1)
+        } else {
+            (
+                self.quadrant(node, node_box, old_box),
+                self.quadrant(node, node_box, new_box),
+            )
+        };
+        if old_i != new_i {
+            match self.remove_entry(node, depth, node_box, old_box, id) {
+                Some(mut entry) => {
+                    entry.u_box = new_box.clone();
+                    self.add_entry(node, depth, node_box, entry);
+                    true
+                }
+                None => false,
+            }
+        } else if old_i >= 0 {
+            match self.nodes[node].child_box(node_box, old_i) {
+                Some(bx) => {
+                    let child = self.nodes[node].child(old_i);
+                    self.update_entry(child, depth + 1, &bx, id, old_box, new_box)
+                }
+                None => false,
             }
+        } else {
+            match self.nodes[node].values.iter_mut().find(|x| x.id == id) {
+                Some(entry) => {
+                    entry.u_box = new_box.clone();
+                    true
+                }
+                None => false,
+            }
+        }
+    }
 
-            println!();
-            level += 1;
+    fn node_stats(&self, node: usize, depth: u32, stats: &mut TreeStats, leaf_elems: &mut usize) {
+        let x = &self.nodes[node];
+        stats.node_count += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+        if stats.elems_per_depth.len() <= depth as usize {
+            stats.elems_per_depth.resize(depth as usize + 1, 0);
+        }
+        stats.elems_per_depth[depth as usize] += x.values.len();
+        if x.is_leaf() {
+            stats.leaf_count += 1;
+            *leaf_elems += x.values.len();
+        } else {
+            for i in 0..4 {
+                self.node_stats(x.child(i), depth + 1, stats, leaf_elems);
+            }
         }
     }
 
-    fn add(
-        &mut self,
+    fn node_to_json(&self, node: usize, init_box: &QuadBox, depth: u32, ret: &mut String) {
+        let x = &self.nodes[node];
+        ret.push_str(&format!(
+            "{{\"box\":{},\"depth\":{},\"elems\":{},\"children\":[",
+            init_box.to_json(),
+            depth,
+            x.values.len()
+        ));
+        if !x.is_leaf() {
+            for i in 0..4 {
+                if i > 0 {
+                    ret.push(',');
+                }
+                if let Some(y) = x.child_box(init_box, i) {
+                    self.node_to_json(x.child(i), &y, depth + 1, ret);
+                }
+            }
+        }
+        ret.push_str("]}");
+    }
+
+    fn debug_node(
+        &self,
+        node: usize,
         depth: u32,
-        u_box: &QuadBox,
-        elem: &Box<dyn TreeObject>,
-        max_depth: u32,
-        max_num: usize,
+        init_box: &QuadBox,
+        visited: &[bool],
+        ret: &mut Vec<DebugCell>,
     ) {
-        match &mut self.0 {
-            Some(x) => {
-                if x.children[0].0.is_none() {
-                    if depth >= max_depth || x.values.len() < max_num {
-                        x.values.push(dyn_clone::clone_box(&**elem));
-                    } else {
-                        Self::split(x, &u_box, &elem);
-                        self.add(depth, u_box, elem, max_depth, max_num);
-                    }
-                } else {
-                    let i = Self::get_quadrant(&u_box, &elem);
-                    if i >= 0 {
-                        let new_box = Self::compute_box(&u_box, i);
-                        match new_box {
-                            Some(bx) => {
-                                x.children[i as usize].add(depth + 1, &bx, elem, max_depth, max_num)
-                            }
-                            None => println!("Compute box is None!"),
-                        }
-                    } else {
-                        x.values.push(dyn_clone::clone_box(&**elem));
-                    }
+        let x = &self.nodes[node];
+        ret.push(DebugCell {
+            u_box: init_box.clone(),
+            depth,
+            num_of_elems: x.values.len(),
+            visited: visited.get(node).copied().unwrap_or(false),
+        });
+        if !x.is_leaf() {
+            for i in 0..4 {
+                if let Some(y) = x.child_box(init_box, i) {
+                    self.debug_node(x.child(i), depth + 1, &y, visited, ret);
                 }
             }
-            None => {
-                self.0 = Some(Box::new(Node::new()));
-                self.add(depth, u_box, elem, max_depth, max_num);
+        }
+    }
+
+    fn get_node_boxes(&self, node: usize, init_box: &QuadBox, boxes: &mut Vec<Option<QuadBox>>) {
+        boxes.push(Some(init_box.clone()));
+        if !self.nodes[node].is_leaf() {
+            for i in 0..4 {
+                let bx_1 = self.nodes[node].child_box(init_box, i);
+                self.get_node_boxes(self.nodes[node].child(i), bx_1.as_ref().unwrap(), boxes);
             }
         }
     }
 
-    fn split(node: &mut Box<Node>, u_box: &QuadBox, elem: &Box<dyn TreeObject>) {
-        for n in node.children.iter_mut() {
-            n.0 = Some(Box::new(Node::new()));
+    fn visit<F>(
+        &self,
+        node: usize,
+        init_box: &QuadBox,
+        region: &impl Fn(&QuadBox) -> bool,
+        visitor: &mut F,
+    ) -> ControlFlow<()>
+    where
+        F: FnMut(ObjectId, &T) -> ControlFlow<()>,
+    {
+        let x = &self.nodes[node];
+        for n in x.values.iter() {
+            if region(&n.u_box) {
+                visitor(n.id, self.object(n.id))?;
+            }
         }
 
-        for (idx, child) in node.children.iter_mut().enumerate() {
-            let mut tmp = node
-                .values
-                .extract_if(|x| {
-                    let i = Self::get_quadrant(u_box, x);
-                    i == idx as i32
-                })
-                .collect::<Vec<_>>();
-            match &mut child.0 {
-                Some(x) => x.values.append(&mut tmp),
-                None => println!("Child is None"),
+        if !x.is_leaf() {
+            for i in 0..4 {
+                if let Some(y) = x.child_box(init_box, i) {
+                    if region(&self.search_box(&y)) {
+                        self.visit(x.child(i), &y, region, visitor)?;
+                    }
+                }
             }
         }
+        ControlFlow::Continue(())
     }
 
-    fn get_quadrant(node_box: &QuadBox, elem: &Box<dyn TreeObject>) -> i32 {
-        let center = node_box.get_center();
-        let elem_box = elem.get_box();
-        if elem_box.get_right_x() < center.x {
-            if elem_box.get_bottom_y() < center.y {
-                return 0;
-            } else if elem_box.y >= center.y {
-                return 2;
-            } else {
-                return -1;
+    fn query_node<'a>(
+        &'a self,
+        node: usize,
+        init_box: &QuadBox,
+        u_box: &QuadBox,
+        ret_elems: &mut Vec<&'a Entry>,
+        mut visited: Option<&mut Vec<u32>>,
+    ) {
+        if let Some(ret) = visited.as_deref_mut() {
+            ret.push(node as u32);
+        }
+        let x = &self.nodes[node];
+        for n in x.values.iter() {
+            if u_box.intersects(&n.u_box) {
+                ret_elems.push(n);
             }
-        } else if elem_box.x >= center.x {
-            if elem_box.get_bottom_y() < center.y {
-                return 1;
-            } else if elem_box.y >= center.y {
-                return 3;
-            } else {
-                return -1;
+        }
+
+        if !x.is_leaf() {
+            for i in 0..4 {
+                if let Some(y) = x.child_box(init_box, i) {
+                    if u_box.intersects(&self.search_box(&y)) {
+                        self.query_node(x.child(i), &y, u_box, ret_elems, visited.as_deref_mut());
+                    }
+                }
             }
-        } else {
-            return -1;
         }
     }
 
-    fn compute_box(node_box: &QuadBox, idx: i32) -> Option<QuadBox> {
-        let origin = Vector2 {
-            x: node_box.x,
-            y: node_box.y,
-        };
-        let child_size = node_box.get_size() / 2.0;
-        match idx {
-            0 => Some(QuadBox::new(origin.x, origin.y, child_size.x, child_size.y)),
-            1 => Some(QuadBox::new(
-                origin.x + child_size.x,
-                origin.y,
-                child_size.x,
-                child_size.y,
-            )),
-            2 => Some(QuadBox::new(
-                origin.x,
-                origin.y + child_size.y,
-                child_size.x,
-                child_size.y,
-            )),
-            3 => Some(QuadBox::new(
-                origin.x + child_size.x,
-                origin.y + child_size.y,
-                child_size.x,
-                child_size.y,
-            )),
-            _ => None,
+    fn pairs_node<'a>(
+        &'a self,
+        node: usize,
+        init_box: &QuadBox,
+        ancestors: &[&'a Entry],
+        ret: &mut Vec<(ObjectId, ObjectId)>,
+    ) {
+        let x = &self.nodes[node];
+        for (i, a) in x.values.iter().enumerate() {
+            for b in ancestors.iter() {
+                if a.u_box.overlaps(&b.u_box) {
+                    ret.push((b.id, a.id));
+                }
+            }
+            for b in x.values[i + 1..].iter() {
+                if a.u_box.overlaps(&b.u_box) {
+                    ret.push((a.id, b.id));
+                }
+            }
+        }
+
+        if x.is_leaf() {
+            return;
+        }
+        let boxes: Vec<Option<QuadBox>> = (0..4).map(|i| x.child_box(init_box, i)).collect();
+        for (i, y) in boxes.iter().enumerate() {
+            let Some(y) = y else {
+                continue;
+            };
+            let search = self.search_box(y);
+            let mut inherited: Vec<&Entry> = ancestors
+                .iter()
+                .copied()
+                .chain(x.values.iter())
+                .filter(|e| e.u_box.intersects(&search))
+                .collect();
+            // Loose cells overlap their siblings, so entries of the earlier
+            // siblings are carried down as well and every pair is seen once.
+            if self.looseness > 1.0 {
+                for (j, z) in boxes[..i].iter().enumerate() {
+                    if let Some(z) = z {
+                        self.query_node(x.child(j as i32), z, &search, &mut inherited, None);
+                    }
+                }
+            }
+            self.pairs_node(x.child(i as i32), y, &inherited, ret);
         }
     }
 
-    fn query(&mut self, init_box: &QuadBox, u_box: &QuadBox, ret_elems: &mut Vec<Option<Vector2>>) {
-        if let Some(x) = &mut self.0 {
-            for n in x.values.iter_mut() {
-                let bx = u_box.minkowski_difference(&n.get_box());
-                if u_box.intersects(&n.get_box()) {
-                    ret_elems.push(u_box.pen_vector(&n.get_box(), &bx));
+    #[allow(clippy::too_many_arguments)]
+    fn raycast_node(
+        &self,
+        node: usize,
+        init_box: &QuadBox,
+        origin: Vec2,
+        dir: Vec2,
+        max_dist: &mut f32,
+        all: bool,
+        hits: &mut Vec<RayHit>,
+    ) {
+        let x = &self.nodes[node];
+        for n in x.values.iter() {
+            if let Some((t, normal)) = n.u_box.ray_intersection(origin, dir, *max_dist) {
+                if !all {
+                    *max_dist = t;
+                    hits.clear();
                 }
+                hits.push(RayHit {
+                    id: n.id,
+                    point: origin + dir * t,
+                    normal,
+                    distance: t,
+                });
             }
+        }
 
-            for (idx, n) in x.children.iter_mut().enumerate() {
-                if let Some(x) = &n.0 {
-                    let child_box = Self::compute_box(init_box, idx as i32);
-                    if let Some(y) = child_box {
-                        if u_box.intersects(&y) {
-                            n.query(&y, u_box, ret_elems);
-                        }
-                    }
+        if x.is_leaf() {
+            return;
+        }
+        let mut cells: Vec<(f32, i32, QuadBox)> = Vec::new();
+        for i in 0..4 {
+            if let Some(y) = x.child_box(init_box, i) {
+                let search = self.search_box(&y);
+                if let Some((t, _)) = search.ray_intersection(origin, dir, *max_dist) {
+                    cells.push((t, i, y));
+                }
+            }
+        }
+        cells.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (t, i, y) in cells.iter() {
+            if *t <= *max_dist {
+                self.raycast_node(x.child(*i), y, origin, dir, max_dist, all, hits);
+            }
+        }
+    }
+}
+
+impl<T: std::fmt::Display> QuadTree<T> {
+    fn node_to_string(&self, node: usize) -> String {
+        let mut ret = String::from("[");
+        for n in self.nodes[node].values.iter() {
+            ret += &self.object(n.id).to_string();
+        }
+        ret + "]"
+    }
+
+    fn print_tree(&self) {
+        let mut queue: VecDeque<Vec<usize>> = VecDeque::new();
+        let mut level: u32 = 0;
+        if !self.nodes[ROOT].is_leaf() {
+            queue.push_back((0..4).map(|i| self.nodes[ROOT].child(i)).collect());
+        }
+        println!("root: {}", self.node_to_string(ROOT));
+
+        while !queue.is_empty() {
+            let mut vc: Vec<usize> = Vec::new();
+            let tmp = queue.pop_front().unwrap();
+            print!("{} level_{}: ", tmp.len(), level);
+
+            for n in tmp.iter() {
+                print!("{}", self.node_to_string(*n));
+                if !self.nodes[*n].is_leaf() {
+                    vc.extend((0..4).map(|i| self.nodes[*n].child(i)));
                 }
             }
+            if !vc.is_empty() {
+                queue.push_back(vc);
+            }
+
+            println!();
+            level += 1;
         }
     }
 }
 
-impl QuadTree {
-    const MAX_DEPTH: u32 = 128;
-    const MAX_NUM_OF_ELEMS: usize = 4;
+impl<T: Bounded> QuadTree<T> {
     pub fn new(width: f32, height: f32) -> Self {
-        Self {
-            root: Subtree::new(),
-            u_box: QuadBox::new(0.0, 0.0, width, height),
-            max_depth: Self::MAX_DEPTH,
-            max_num_of_elems: Self::MAX_NUM_OF_ELEMS,
+        QuadTreeBuilder::new().size(width, height).build()
+    }
+    pub fn new_loose(width: f32, height: f32, looseness: f32) -> Self {
+        QuadTreeBuilder::new()
+            .size(width, height)
+            .looseness(looseness)
+            .build()
+    }
+    pub fn from_slice(items: &[T]) -> Self
+    where
+        T: Clone,
+    {
+        QuadTreeBuilder::new().build_from_slice(items)
+    }
+    pub fn add(&mut self, elem: T) -> ObjectId {
+        let u_box = elem.get_box();
+        let id = self.slab.insert(u_box.clone(), elem);
+        self.grow_to_fit(&u_box);
+        let root_box = self.u_box.clone();
+        self.add_entry(ROOT, 0, &root_box, Entry { id, u_box });
+        id
+    }
+    pub fn remove(&mut self, id: ObjectId) -> Option<T> {
+        let elem_box = self.slab.slot(id)?.u_box.clone();
+        let root_box = self.u_box.clone();
+        self.remove_entry(ROOT, 0, &root_box, &elem_box, id)?;
+        self.slab.remove(id)
+    }
+    pub fn compact(&mut self) {
+        let root_box = self.u_box.clone();
+        self.compact_node(ROOT, 0, &root_box);
+    }
+    pub fn set_policy(&mut self, policy: impl SplitPolicy + Send + Sync + 'static) {
+        self.policy = Box::new(policy);
+    }
+    pub fn update(&mut self, id: ObjectId, new_box: QuadBox) -> bool {
+        let old_box = match self.slab.slot(id) {
+            Some(x) => x.u_box.clone(),
+            None => return false,
+        };
+        self.slab.set_box(id.index, new_box.clone());
+        self.grow_to_fit(&new_box);
+        let root_box = self.u_box.clone();
+        self.update_entry(ROOT, 0, &root_box, id, &old_box, &new_box)
+    }
+    pub fn get(&self, id: ObjectId) -> Option<&T> {
+        self.slab.get(id)
+    }
+    pub fn get_mut(&mut self, id: ObjectId) -> Option<&mut T> {
+        self.slab.get_mut(id)
+    }
+    pub fn len(&self) -> usize {
+        self.slab.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.slab.len() == 0
+    }
+    pub fn stats(&self) -> TreeStats {
+        let mut ret = TreeStats::default();
+        let mut leaf_elems = 0;
+        self.node_stats(ROOT, 0, &mut ret, &mut leaf_elems);
+        if !self.nodes[ROOT].is_leaf() {
+            ret.root_straddlers = self.nodes[ROOT].values.len();
         }
+        ret.avg_elems_per_leaf = leaf_elems as f32 / ret.leaf_count as f32;
+        ret
     }
-    pub fn add(&mut self, elem: &Box<dyn TreeObject>) {
-        self.root
-            .add(0, &self.u_box, elem, self.max_depth, self.max_num_of_elems);
+    pub fn to_json(&self) -> String {
+        let mut ret = format!(
+            "{{\"len\":{},\"policy\":{},\"looseness\":{},\"root\":",
+            self.slab.len(),
+            json_string(&format!("{:?}", self.policy)),
+            json_num(self.looseness)
+        );
+        self.node_to_json(ROOT, &self.u_box, 0, &mut ret);
+        ret.push('}');
+        ret
     }
-    pub fn print(&mut self) {
-        self.root.print_tree();
+    /// Lists every cell of the tree; cells whose node is in `traced` (as
+    /// filled by `query_traced`) are marked as visited.
+    pub fn debug_cells(&self, traced: &[u32]) -> Vec<DebugCell> {
+        let mut visited = vec![false; self.nodes.len()];
+        for n in traced.iter() {
+            if let Some(x) = visited.get_mut(*n as usize) {
+                *x = true;
+            }
+        }
+        let mut ret: Vec<DebugCell> = Vec::new();
+        self.debug_node(ROOT, 0, &self.u_box, &visited, &mut ret);
+        ret
     }
-    pub fn get_boxes(&mut self) -> Vec<Option<QuadBox>> {
+    pub fn get_boxes(&self) -> Vec<Option<QuadBox>> {
         let mut ret: Vec<Option<QuadBox>> = Vec::new();
-        self.root.get_boxes(&self.u_box, &mut ret);
+        self.get_node_boxes(ROOT, &self.u_box, &mut ret);
         ret
     }
-    pub fn draw_tree(&mut self, draw_handler: &mut RaylibDrawHandle) {
-        self.root.draw_tree(draw_handler);
+    pub fn iter(&self) -> impl Iterator<Item = (ObjectId, &T)> {
+        self.slab.iter()
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (ObjectId, &mut T)> {
+        self.slab.iter_mut()
+    }
+    pub fn iter_in_box(&self, u_box: &QuadBox) -> impl Iterator<Item = (ObjectId, &T)> {
+        let mut ret: Vec<ObjectId> = Vec::new();
+        let _ = self.visit(ROOT, &self.u_box, &|x| x.intersects(u_box), &mut |id, _| {
+            ret.push(id);
+            ControlFlow::Continue(())
+        });
+        ret.into_iter().map(|id| (id, self.object(id)))
+    }
+    pub fn drain(&mut self) -> impl Iterator<Item = (ObjectId, T)> {
+        let ret = self.slab.drain();
+        self.nodes = vec![Node::new()];
+        self.free_nodes.clear();
+        ret.into_iter()
+    }
+    pub fn retain<F: FnMut(ObjectId, &T) -> bool>(&mut self, mut pred: F) {
+        let removed: Vec<ObjectId> = self
+            .iter()
+            .filter(|(id, x)| !pred(*id, x))
+            .map(|(id, _)| id)
+            .collect();
+        for id in removed {
+            self.remove(id);
+        }
     }
-    pub fn query(&mut self, elem: &Box<impl TreeObject>) -> Vec<Option<Vector2>> {
-        let mut ret: Vec<Option<Vector2>> = Vec::new();
-        self.root.query(&self.u_box, &elem.get_box(), &mut ret);
+    pub fn query(&self, elem: &impl Bounded) -> Vec<Contact> {
+        self.query_with(elem, None)
+    }
+    /// Same as `query`, but also appends the nodes it descended into to
+    /// `visited`, for `debug_cells`.
+    pub fn query_traced(&self, elem: &impl Bounded, visited: &mut Vec<u32>) -> Vec<Contact> {
+        self.query_with(elem, Some(visited))
+    }
+    fn query_with(&self, elem: &impl Bounded, visited: Option<&mut Vec<u32>>) -> Vec<Contact> {
+        let u_box = elem.get_box();
+        let shape = elem.get_shape();
+        let mut hits: Vec<&Entry> = Vec::new();
+        self.query_node(ROOT, &self.u_box, &u_box, &mut hits, visited);
+        hits.into_iter()
+            .filter_map(|n| {
+                let other_shape = self.object(n.id).get_shape();
+                penetration(&u_box, shape, &n.u_box, other_shape)
+                    .map(|pen| Contact::new(Some(n.id), n.u_box.clone(), pen))
+            })
+            .collect()
+    }
+    #[cfg(feature = "parallel")]
+    pub fn par_query<E: Bounded + Sync>(&self, elems: &[E]) -> Vec<Vec<Contact>>
+    where
+        T: Sync,
+    {
+        use rayon::prelude::*;
+        elems.par_iter().map(|x| self.query(x)).collect()
+    }
+    pub fn overlapping_pairs(&self) -> impl Iterator<Item = (ObjectId, ObjectId)> {
+        let mut ret: Vec<(ObjectId, ObjectId)> = Vec::new();
+        self.pairs_node(ROOT, &self.u_box, &[], &mut ret);
+        ret.into_iter()
+    }
+    pub fn raycast(&self, origin: Vec2, dir: Vec2, max_dist: f32) -> Option<RayHit> {
+        let mut ret = self.cast(origin, dir, max_dist, false);
+        ret.pop()
+    }
+    pub fn segment_cast(&self, a: Vec2, b: Vec2) -> Option<RayHit> {
+        let len = (b - a).length();
+        let mut ret = self.cast(a, b - a, len, false);
+        ret.pop()
+    }
+    pub fn all_hits(&self, origin: Vec2, dir: Vec2, max_dist: f32) -> Vec<RayHit> {
+        self.cast(origin, dir, max_dist, true)
+    }
+    pub fn segment_all_hits(&self, a: Vec2, b: Vec2) -> Vec<RayHit> {
+        let len = (b - a).length();
+        self.cast(a, b - a, len, true)
+    }
+    fn cast(&self, origin: Vec2, dir: Vec2, max_dist: f32, all: bool) -> Vec<RayHit> {
+        let mut ret: Vec<RayHit> = Vec::new();
+        if dir.length_sqr() == 0.0 {
+            return ret;
+        }
+        let mut max_dist = max_dist;
+        self.raycast_node(
+            ROOT,
+            &self.u_box,
+            origin,
+            dir.normalized(),
+            &mut max_dist,
+            all,
+            &mut ret,
+        );
+        ret.sort_by(|a, b| a.distance.total_cmp(&b.distance));
         ret
     }
+    pub fn query_point<F>(&self, point: Vec2, mut visitor: F) -> ControlFlow<()>
+    where
+        F: FnMut(&T) -> ControlFlow<()>,
+    {
+        self.visit(
+            ROOT,
+            &self.u_box,
+            &|x| x.contains_point(point),
+            &mut |_, x| visitor(x),
+        )
+    }
+    pub fn query_box<F>(&self, u_box: &QuadBox, mut visitor: F) -> ControlFlow<()>
+    where
+        F: FnMut(&T) -> ControlFlow<()>,
+    {
+        self.visit(ROOT, &self.u_box, &|x| x.intersects(u_box), &mut |_, x| {
+            visitor(x)
+        })
+    }
+    pub fn query_circle<F>(&self, center: Vec2, radius: f32, mut visitor: F) -> ControlFlow<()>
+    where
+        F: FnMut(&T) -> ControlFlow<()>,
+    {
+        self.visit(
+            ROOT,
+            &self.u_box,
+            &|x| x.distance_to_point(center) <= radius,
+            &mut |_, x| visitor(x),
+        )
+    }
+    pub fn nearest(&self, point: Vec2) -> Option<Neighbour> {
+        self.k_nearest(point, 1, f32::INFINITY).pop()
+    }
+    pub fn k_nearest(&self, point: Vec2, k: usize, max_radius: f32) -> Vec<Neighbour> {
+        let mut ret: Vec<Neighbour> = Vec::new();
+        let mut heap: BinaryHeap<HeapItem> = BinaryHeap::new();
+        if k == 0 {
+            return ret;
+        }
+        heap.push(HeapItem {
+            distance: 0.0,
+            candidate: Candidate::Cell(ROOT, self.u_box.clone()),
+        });
+        while let Some(item) = heap.pop() {
+            if item.distance > max_radius {
+                break;
+            }
+            match item.candidate {
+                Candidate::Elem(id) => {
+                    ret.push(Neighbour {
+                        id,
+                        distance: item.distance,
+                    });
+                    if ret.len() == k {
+                        break;
+                    }
+                }
+                Candidate::Cell(node, cell) => {
+                    let x = &self.nodes[node];
+                    for n in x.values.iter() {
+                        heap.push(HeapItem {
+                            distance: n.u_box.distance_to_point(point),
+                            candidate: Candidate::Elem(n.id),
+                        });
+                    }
+                    if !x.is_leaf() {
+                        for i in 0..4 {
+                            if let Some(y) = x.child_box(&cell, i) {
+                                heap.push(HeapItem {
+                                    distance: self.search_box(&y).distance_to_point(point),
+                                    candidate: Candidate::Cell(x.child(i), y),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        ret
+    }
+}
+
+impl<T: Bounded> Broadphase<T> for QuadTree<T> {
+    fn insert(&mut self, elem: T) -> ObjectId {
+        self.add(elem)
+    }
+    fn get(&self, id: ObjectId) -> Option<&T> {
+        QuadTree::get(self, id)
+    }
+    fn remove(&mut self, id: ObjectId) -> Option<T> {
+        QuadTree::remove(self, id)
+    }
+    fn update(&mut self, id: ObjectId, new_box: QuadBox) -> bool {
+        QuadTree::update(self, id, new_box)
+    }
+    fn query(&self, elem: &impl Bounded) -> Vec<Contact> {
+        QuadTree::query(self, elem)
+    }
+    fn pairs(&self) -> Vec<(ObjectId, ObjectId)> {
+        self.overlapping_pairs().collect()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Serialize> Serialize for QuadTree<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TreeRef {
+            u_box: &self.u_box,
+            looseness: self.looseness,
+            nodes: &self.nodes,
+            free_nodes: &self.free_nodes,
+            items: self.slab.slots(),
+            free_items: self.slab.free_items(),
+            policy: PolicyRepr::new(self.policy.as_ref()),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for QuadTree<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = TreeRepr::<T>::deserialize(deserializer)?;
+        Self::from_repr(repr, None).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> QuadTree<T> {
+    pub fn restore_with_policy<'de, D: Deserializer<'de>>(
+        deserializer: D,
+        policy: impl SplitPolicy + Send + Sync + 'static,
+    ) -> Result<Self, D::Error>
+    where
+        T: Deserialize<'de>,
+    {
+        let repr = TreeRepr::<T>::deserialize(deserializer)?;
+        Self::from_repr(repr, Some(Box::new(policy))).map_err(D::Error::custom)
+    }
+
+    fn from_repr(
+        repr: TreeRepr<T>,
+        policy: Option<Box<dyn SplitPolicy + Send + Sync>>,
+    ) -> Result<Self, &'static str> {
+        let policy = match policy {
+            Some(x) => x,
+            None => repr
+                .policy
+                .ok_or("snapshot has no built-in split policy")?
+                .into_policy()?,
+        };
+        let tree = QuadTree {
+            nodes: repr.nodes,
+            free_nodes: repr.free_nodes,
+            slab: Slab::from_parts(repr.items, repr.free_items),
+            u_box: repr.u_box,
+            policy,
+            looseness: repr.looseness,
+        };
+        tree.validate()?;
+        Ok(tree)
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        let nodes_len = self.nodes.len();
+        if nodes_len == 0 {
+            return Err("snapshot has no root node");
+        }
+        // Every node has to be either reachable from the root exactly once or
+        // part of a free block, anything else is a cycle, a shared subtree or a leak.
+        let mut seen = vec![false; nodes_len];
+        seen[ROOT] = true;
+        let mut stack = vec![ROOT];
+        while let Some(node) = stack.pop() {
+            let x = &self.nodes[node];
+            if x.is_leaf() {
+                continue;
+            }
+            let first = x.children as usize;
+            if first + 4 > nodes_len {
+                return Err("node children out of range");
+            }
+            for (i, x) in seen[first..first + 4].iter_mut().enumerate() {
+                if std::mem::replace(x, true) {
+                    return Err("node is reachable more than once");
+                }
+                stack.push(first + i);
+            }
+        }
+        for first in self.free_nodes.iter().map(|x| *x as usize) {
+            if first + 4 > nodes_len {
+                return Err("free nodes out of range");
+            }
+            let block = self.nodes[first..first + 4].iter();
+            for (x, seen) in block.zip(seen[first..first + 4].iter_mut()) {
+                if std::mem::replace(seen, true) || !x.is_leaf() || !x.values.is_empty() {
+                    return Err("free node is in use");
+                }
+            }
+        }
+        if seen.contains(&false) {
+            return Err("node is unreachable");
+        }
+
+        let slots = self.slab.slots();
+        let mut entries = vec![0; slots.len()];
+        for (node, x) in self.nodes.iter().enumerate() {
+            for n in x.values.iter() {
+                if !self.slab.slot(n.id).is_some_and(|x| x.u_box == n.u_box) {
+                    return Err("entry does not match an item");
+                }
+                if self.route(&n.u_box) != node {
+                    return Err("entry is not in the node its box routes to");
+                }
+                entries[n.id.index as usize] += 1;
+            }
+        }
+        let mut free = vec![false; slots.len()];
+        for n in self.slab.free_items().iter() {
+            match free.get_mut(*n as usize) {
+                Some(x) if !*x => *x = true,
+                _ => return Err("free items out of range or repeated"),
+            }
+        }
+        for ((slot, count), free) in slots.iter().zip(entries).zip(free) {
+            let live = slot.object.is_some();
+            if live == free || (live && count != 1) {
+                return Err("items, entries and free items disagree");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Bounded + std::fmt::Display> QuadTree<T> {
+    pub fn print(&self) {
+        self.print_tree();
+    }
 }
@@ -0,0 +1,58 @@
+use crate::quadtree::{Bounded, QuadBox, QuadTree};
+use dyn_clone::DynClone;
+use raylib::prelude::{Color, RaylibDraw, RaylibDrawHandle, Rectangle};
+
+pub trait Drawable {
+    fn draw(&self, draw_handler: &mut RaylibDrawHandle);
+}
+
+pub trait TreeObject: Bounded + Drawable + std::fmt::Display + DynClone {}
+
+impl<T: Bounded + Drawable + std::fmt::Display + DynClone> TreeObject for T {}
+
+dyn_clone::clone_trait_object!(TreeObject);
+
+fn cell_rect(u_box: &QuadBox) -> Rectangle {
+    Rectangle {
+        x: u_box.get_lefttop().x,
+        y: u_box.get_lefttop().y,
+        width: u_box.get_size().x,
+        height: u_box.get_size().y,
+    }
+}
+
+impl<T: Drawable + ?Sized> Drawable for Box<T> {
+    fn draw(&self, draw_handler: &mut RaylibDrawHandle) {
+        (**self).draw(draw_handler);
+    }
+}
+
+impl<T: Bounded + Drawable> QuadTree<T> {
+    pub fn draw_tree(&self, draw_handler: &mut RaylibDrawHandle) {
+        for (_, x) in self.iter() {
+            x.draw(draw_handler);
+        }
+    }
+
+    pub fn draw_debug(&self, draw_handler: &mut RaylibDrawHandle, traced: &[u32]) {
+        let cells = self.debug_cells(traced);
+        let max_elems = cells
+            .iter()
+            .map(|x| x.num_of_elems)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        for n in cells.iter() {
+            let rec = cell_rect(&n.u_box);
+            if n.num_of_elems > 0 {
+                let alpha = 0.5 * n.num_of_elems as f32 / max_elems as f32;
+                draw_handler.draw_rectangle_rec(rec, Color::BLUE.fade(alpha));
+            }
+            draw_handler.draw_rectangle_lines_ex(rec, 1.0, Color::DARKGRAY);
+        }
+        for n in cells.iter().filter(|x| x.visited) {
+            let rec = cell_rect(&n.u_box);
+            draw_handler.draw_rectangle_lines_ex(rec, 3.0, Color::RED);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::quadtree::{ObjectId, QuadBox};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(crate) struct Slot<T> {
+    pub(crate) generation: u32,
+    pub(crate) u_box: QuadBox,
+    pub(crate) object: Option<T>,
+}
+
+// Object storage shared by every spatial index. A removed slot bumps its
+// generation before it is reused, so stale ids never reach the next tenant.
+pub(crate) struct Slab<T> {
+    items: Vec<Slot<T>>,
+    free_items: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    pub(crate) fn new() -> Self {
+        Slab {
+            items: Vec::new(),
+            free_items: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub(crate) fn from_parts(items: Vec<Slot<T>>, free_items: Vec<u32>) -> Self {
+        Slab {
+            len: items.iter().filter(|x| x.object.is_some()).count(),
+            items,
+            free_items,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn slots(&self) -> &[Slot<T>] {
+        &self.items
+    }
+
+    pub(crate) fn free_items(&self) -> &[u32] {
+        &self.free_items
+    }
+
+    pub(crate) fn id(&self, index: u32) -> ObjectId {
+        ObjectId {
+            index,
+            generation: self.items[index as usize].generation,
+        }
+    }
+
+    pub(crate) fn u_box(&self, index: u32) -> &QuadBox {
+        &self.items[index as usize].u_box
+    }
+
+    pub(crate) fn item(&self, index: u32) -> &Slot<T> {
+        &self.items[index as usize]
+    }
+
+    pub(crate) fn slot(&self, id: ObjectId) -> Option<&Slot<T>> {
+        self.items
+            .get(id.index as usize)
+            .filter(|x| x.generation == id.generation && x.object.is_some())
+    }
+
+    pub(crate) fn get(&self, id: ObjectId) -> Option<&T> {
+        self.slot(id)?.object.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, id: ObjectId) -> Option<&mut T> {
+        self.slot(id)?;
+        self.items[id.index as usize].object.as_mut()
+    }
+
+    pub(crate) fn set_box(&mut self, index: u32, u_box: QuadBox) {
+        self.items[index as usize].u_box = u_box;
+    }
+
+    pub(crate) fn insert(&mut self, u_box: QuadBox, elem: T) -> ObjectId {
+        self.len += 1;
+        match self.free_items.pop() {
+            Some(index) => {
+                let slot = &mut self.items[index as usize];
+                slot.u_box = u_box;
+                slot.object = Some(elem);
+                self.id(index)
+            }
+            None => {
+                self.items.push(Slot {
+                    generation: 0,
+                    u_box,
+                    object: Some(elem),
+                });
+                self.id(self.items.len() as u32 - 1)
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, id: ObjectId) -> Option<T> {
+        self.slot(id)?;
+        let slot = &mut self.items[id.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_items.push(id.index);
+        self.len -= 1;
+        slot.object.take()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (ObjectId, &T)> {
+        self.items.iter().enumerate().filter_map(|(index, n)| {
+            let id = ObjectId {
+                index: index as u32,
+                generation: n.generation,
+            };
+            n.object.as_ref().map(|x| (id, x))
+        })
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (ObjectId, &mut T)> {
+        self.items.iter_mut().enumerate().filter_map(|(index, n)| {
+            let id = ObjectId {
+                index: index as u32,
+                generation: n.generation,
+            };
+            n.object.as_mut().map(|x| (id, x))
+        })
+    }
+
+    pub(crate) fn drain(&mut self) -> Vec<(ObjectId, T)> {
+        let mut ret: Vec<(ObjectId, T)> = Vec::with_capacity(self.len);
+        for (index, n) in self.items.iter_mut().enumerate() {
+            if let Some(x) = n.object.take() {
+                ret.push((
+                    ObjectId {
+                        index: index as u32,
+                        generation: n.generation,
+                    },
+                    x,
+                ));
+                n.generation = n.generation.wrapping_add(1);
+                self.free_items.push(index as u32);
+            }
+        }
+        self.len = 0;
+        ret
+    }
+}
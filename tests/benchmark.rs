@@ -1,14 +1,16 @@
 #![allow(dead_code, unused_variables)]
+use my_rusted_balls::broadphase::*;
+use my_rusted_balls::math::Vec2;
 use my_rusted_balls::objects::*;
 use my_rusted_balls::quadtree::*;
 use rand::Rng;
-use raylib::prelude::*;
+use std::ops::ControlFlow;
 use std::time::{Duration, Instant};
 
 #[cfg(test)]
 
-fn brut_force_find(elems: &Vec<Box<dyn TreeObject>>, target: QuadBox) -> Vec<Option<Vector2>> {
-    let mut ret: Vec<Option<Vector2>> = Vec::new();
+fn brut_force_find(elems: &Vec<Rectangle>, target: QuadBox) -> Vec<Option<Vec2>> {
+    let mut ret: Vec<Option<Vec2>> = Vec::new();
     for n in elems.iter() {
         let tmp = n.get_box();
         let bx = tmp.minkowski_difference(&target);
@@ -38,8 +40,12 @@ fn quadtree_benchmark() {
         MIN_REC_HEIGHT,
         MAX_REC_HEIGHT,
     );
+    let mut loose_tree = QuadTree::new_loose(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32, 2.0);
+    let mut grid = UniformGrid::new(MAX_REC_WIDTH as f32).unwrap();
     for n in elems.iter() {
-        tree.add(n);
+        tree.add(n.clone());
+        loose_tree.add(n.clone());
+        grid.insert(n.clone());
     }
     let mut target = Box::new(
         RectangleBuilder::new()
@@ -52,8 +58,11 @@ fn quadtree_benchmark() {
     let mut rng = rand::thread_rng();
     let mut total_brut: Duration = Duration::new(0, 0);
     let mut total_tree: Duration = Duration::new(0, 0);
+    let mut total_visit: Duration = Duration::new(0, 0);
+    let mut total_loose: Duration = Duration::new(0, 0);
+    let mut total_grid: Duration = Duration::new(0, 0);
     for n in 0..ATTEMPTS {
-        target.set_coordinate(Vector2 {
+        target.set_coordinate(Vec2 {
             x: rng.gen_range(0..WINDOW_WIDTH - 50) as f32,
             y: rng.gen_range(0..WINDOW_HEIGHT - 50) as f32,
         });
@@ -63,10 +72,380 @@ fn quadtree_benchmark() {
         let now = Instant::now();
         tree.query(&target);
         total_tree += now.elapsed();
+        let now = Instant::now();
+        let mut hits = 0;
+        let _ = tree.query_box(&target.get_box(), |_| {
+            hits += 1;
+            ControlFlow::Continue(())
+        });
+        total_visit += now.elapsed();
+        let now = Instant::now();
+        let found = loose_tree.query(&target).len();
+        total_loose += now.elapsed();
+        let now = Instant::now();
+        assert_eq!(grid.query(&target).len(), found);
+        total_grid += now.elapsed();
     }
     println!(
-        "Total brut: {:.3?} Total tree: {:.3?}",
+        "Total brut: {:.3?} Total tree: {:.3?} Total visit: {:.3?} Total loose: {:.3?} Total grid: {:.3?}",
         total_brut / ATTEMPTS,
-        total_tree / ATTEMPTS
+        total_tree / ATTEMPTS,
+        total_visit / ATTEMPTS,
+        total_loose / ATTEMPTS,
+        total_grid / ATTEMPTS
+    );
+}
+
+#[test]
+fn quadtree_update_benchmark() {
+    const WINDOW_WIDTH: u32 = 1024;
+    const WINDOW_HEIGHT: u32 = 1024;
+    const NUM_OF_OBJECTS: u32 = 100000;
+    const MAX_REC_WIDTH: u32 = 100;
+    const MIN_REC_WIDTH: u32 = 10;
+    const MAX_REC_HEIGHT: u32 = 100;
+    const MIN_REC_HEIGHT: u32 = 10;
+    let mut tree = QuadTree::new(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32);
+    let elems = my_rusted_balls::gen_vec_of_objects(
+        NUM_OF_OBJECTS,
+        WINDOW_WIDTH,
+        WINDOW_HEIGHT,
+        MIN_REC_WIDTH,
+        MAX_REC_WIDTH,
+        MIN_REC_HEIGHT,
+        MAX_REC_HEIGHT,
+    );
+    let ids: Vec<ObjectId> = elems.iter().map(|n| tree.add(n.clone())).collect();
+
+    const ATTEMPTS: u32 = 1000;
+    let mut rng = rand::thread_rng();
+    let now = Instant::now();
+    for n in 0..ATTEMPTS {
+        let idx = rng.gen_range(0..NUM_OF_OBJECTS) as usize;
+        let bx = elems[idx].get_box();
+        let new_box = QuadBox::new(
+            bx.get_lefttop().x + rng.gen_range(-5.0..5.0),
+            bx.get_lefttop().y + rng.gen_range(-5.0..5.0),
+            bx.get_size().x,
+            bx.get_size().y,
+        );
+        assert!(tree.update(ids[idx], new_box));
+    }
+    let total_update = now.elapsed();
+
+    let now = Instant::now();
+    let mut rebuilt = QuadTree::new(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32);
+    for n in elems.iter() {
+        rebuilt.add(n.clone());
+    }
+    let total_rebuild = now.elapsed();
+    println!(
+        "Update: {:.3?} Rebuild: {:.3?}",
+        total_update / ATTEMPTS,
+        total_rebuild
+    );
+}
+
+struct BoxedTree {
+    root: Option<Box<BoxedNode>>,
+    u_box: QuadBox,
+}
+
+struct BoxedNode {
+    values: Vec<Rectangle>,
+    children: [Option<Box<BoxedNode>>; 4],
+}
+
+impl BoxedTree {
+    const MAX_DEPTH: u32 = 128;
+    const MAX_NUM_OF_ELEMS: usize = 4;
+
+    fn quadrant(node_box: &QuadBox, elem_box: &QuadBox) -> Option<usize> {
+        let center = node_box.get_center();
+        let left = elem_box.get_right_x() < center.x;
+        let right = elem_box.get_lefttop().x >= center.x;
+        let top = elem_box.get_bottom_y() < center.y;
+        let bottom = elem_box.get_lefttop().y >= center.y;
+        match (left, right, top, bottom) {
+            (true, _, true, _) => Some(0),
+            (_, true, true, _) => Some(1),
+            (true, _, _, true) => Some(2),
+            (_, true, _, true) => Some(3),
+            _ => None,
+        }
+    }
+
+    fn child_box(node_box: &QuadBox, idx: usize) -> QuadBox {
+        let size = node_box.get_size() / 2.0;
+        let origin = node_box.get_lefttop();
+        QuadBox::new(
+            origin.x + size.x * (idx % 2) as f32,
+            origin.y + size.y * (idx / 2) as f32,
+            size.x,
+            size.y,
+        )
+    }
+
+    fn add(&mut self, elem: Rectangle) {
+        let u_box = self.u_box.clone();
+        let node = self.root.get_or_insert_with(BoxedNode::new);
+        node.add(0, &u_box, elem);
+    }
+
+    fn query(&self, u_box: &QuadBox) -> Vec<Option<Vec2>> {
+        let mut ret: Vec<Option<Vec2>> = Vec::new();
+        if let Some(x) = &self.root {
+            x.query(&self.u_box, u_box, &mut ret);
+        }
+        ret
+    }
+}
+
+impl BoxedNode {
+    fn new() -> Box<Self> {
+        Box::new(BoxedNode {
+            values: Vec::new(),
+            children: [None, None, None, None],
+        })
+    }
+
+    fn add(&mut self, depth: u32, node_box: &QuadBox, elem: Rectangle) {
+        if self.children[0].is_none() {
+            if depth >= BoxedTree::MAX_DEPTH || self.values.len() < BoxedTree::MAX_NUM_OF_ELEMS {
+                self.values.push(elem);
+                return;
+            }
+            for n in self.children.iter_mut() {
+                *n = Some(BoxedNode::new());
+            }
+            for n in std::mem::take(&mut self.values) {
+                match BoxedTree::quadrant(node_box, &n.get_box()) {
+                    Some(i) => self.children[i].as_mut().unwrap().values.push(n),
+                    None => self.values.push(n),
+                }
+            }
+        }
+        match BoxedTree::quadrant(node_box, &elem.get_box()) {
+            Some(i) => {
+                let bx = BoxedTree::child_box(node_box, i);
+                self.children[i].as_mut().unwrap().add(depth + 1, &bx, elem);
+            }
+            None => self.values.push(elem),
+        }
+    }
+
+    fn query(&self, node_box: &QuadBox, u_box: &QuadBox, ret: &mut Vec<Option<Vec2>>) {
+        for n in self.values.iter() {
+            let bx = u_box.minkowski_difference(&n.get_box());
+            if u_box.intersects(&n.get_box()) {
+                ret.push(u_box.pen_vector(&n.get_box(), &bx));
+            }
+        }
+        for (idx, n) in self.children.iter().enumerate() {
+            if let Some(x) = n {
+                let bx = BoxedTree::child_box(node_box, idx);
+                if u_box.intersects(&bx) {
+                    x.query(&bx, u_box, ret);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn quadtree_layout_benchmark() {
+    const WINDOW_WIDTH: u32 = 1024;
+    const WINDOW_HEIGHT: u32 = 1024;
+    const NUM_OF_OBJECTS: u32 = 100000;
+    const MAX_REC_WIDTH: u32 = 100;
+    const MIN_REC_WIDTH: u32 = 10;
+    const MAX_REC_HEIGHT: u32 = 100;
+    const MIN_REC_HEIGHT: u32 = 10;
+    let elems = my_rusted_balls::gen_vec_of_objects(
+        NUM_OF_OBJECTS,
+        WINDOW_WIDTH,
+        WINDOW_HEIGHT,
+        MIN_REC_WIDTH,
+        MAX_REC_WIDTH,
+        MIN_REC_HEIGHT,
+        MAX_REC_HEIGHT,
+    );
+
+    let now = Instant::now();
+    let mut arena = QuadTree::new(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32);
+    for n in elems.iter() {
+        arena.add(n.clone());
+    }
+    let build_arena = now.elapsed();
+
+    let now = Instant::now();
+    let bulk = QuadTree::from_slice(&elems);
+    let build_bulk = now.elapsed();
+
+    let now = Instant::now();
+    let mut boxed = BoxedTree {
+        root: None,
+        u_box: QuadBox::new(0.0, 0.0, WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32),
+    };
+    for n in elems.iter() {
+        boxed.add(n.clone());
+    }
+    let build_boxed = now.elapsed();
+
+    const ATTEMPTS: u32 = 1000;
+    let mut rng = rand::thread_rng();
+    let mut total_arena: Duration = Duration::new(0, 0);
+    let mut total_boxed: Duration = Duration::new(0, 0);
+    let mut total_bulk: Duration = Duration::new(0, 0);
+    for n in 0..ATTEMPTS {
+        let target = RectangleBuilder::new()
+            .coordinate(
+                rng.gen_range(0..WINDOW_WIDTH - 50) as f32,
+                rng.gen_range(0..WINDOW_HEIGHT - 50) as f32,
+            )
+            .size(50.0, 50.0)
+            .build();
+        let now = Instant::now();
+        let found_arena = arena.query(&target).len();
+        total_arena += now.elapsed();
+        let now = Instant::now();
+        let found_boxed = boxed.query(&target.get_box()).len();
+        total_boxed += now.elapsed();
+        assert!(found_arena <= found_boxed);
+        let now = Instant::now();
+        let found_bulk = bulk.query(&target).len();
+        total_bulk += now.elapsed();
+        assert_eq!(found_bulk, found_arena);
+    }
+    println!(
+        "Arena build: {:.3?} query: {:.3?} Boxed build: {:.3?} query: {:.3?} Bulk build: {:.3?} query: {:.3?}",
+        build_arena,
+        total_arena / ATTEMPTS,
+        build_boxed,
+        total_boxed / ATTEMPTS,
+        build_bulk,
+        total_bulk / ATTEMPTS
     );
 }
+
+#[test]
+fn quadtree_pairs_benchmark() {
+    const WINDOW_WIDTH: u32 = 1024;
+    const WINDOW_HEIGHT: u32 = 1024;
+    const NUM_OF_OBJECTS: u32 = 5000;
+    let mut tree = QuadTree::new(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32);
+    let mut loose_tree = QuadTree::new_loose(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32, 2.0);
+    let elems = my_rusted_balls::gen_vec_of_objects(
+        NUM_OF_OBJECTS,
+        WINDOW_WIDTH,
+        WINDOW_HEIGHT,
+        5,
+        20,
+        5,
+        20,
+    );
+    for n in elems.iter() {
+        tree.add(n.clone());
+        loose_tree.add(n.clone());
+    }
+
+    let now = Instant::now();
+    let pairs = tree.overlapping_pairs().count();
+    let total_pairs = now.elapsed();
+
+    let now = Instant::now();
+    assert_eq!(loose_tree.overlapping_pairs().count(), pairs);
+    let total_loose = now.elapsed();
+
+    let now = Instant::now();
+    let mut hits = 0;
+    for n in elems.iter() {
+        hits += tree.query(n).len() - 1;
+    }
+    let total_query = now.elapsed();
+    assert_eq!(pairs * 2, hits);
+    println!(
+        "Pairs: {:.3?} Loose pairs: {:.3?} Query per object: {:.3?}",
+        total_pairs, total_loose, total_query
+    );
+}
+
+fn policy_builders(width: f32, height: f32) -> Vec<(&'static str, QuadTreeBuilder)> {
+    vec![
+        (
+            "count",
+            QuadTreeBuilder::new()
+                .size(width, height)
+                .policy(CountPolicy::default()),
+        ),
+        (
+            "min cell",
+            QuadTreeBuilder::new()
+                .size(width, height)
+                .policy(MinCellSizePolicy::new(8.0, 4).unwrap()),
+        ),
+        (
+            "median",
+            QuadTreeBuilder::new()
+                .size(width, height)
+                .policy(MedianPolicy::default()),
+        ),
+    ]
+}
+
+#[test]
+fn quadtree_policy_benchmark() {
+    const WINDOW_WIDTH: u32 = 1024;
+    const WINDOW_HEIGHT: u32 = 1024;
+    const NUM_OF_OBJECTS: u32 = 50000;
+    const ATTEMPTS: u32 = 1000;
+    let uniform = my_rusted_balls::gen_vec_of_objects(
+        NUM_OF_OBJECTS,
+        WINDOW_WIDTH,
+        WINDOW_HEIGHT,
+        1,
+        20,
+        1,
+        20,
+    );
+    let clustered = my_rusted_balls::gen_vec_of_objects(NUM_OF_OBJECTS, 64, 64, 1, 4, 1, 4);
+
+    for (scene, elems, range) in [
+        ("uniform", &uniform, WINDOW_WIDTH),
+        ("clustered", &clustered, 64),
+    ] {
+        for (name, builder) in policy_builders(WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32) {
+            let now = Instant::now();
+            let mut tree = builder.build();
+            for n in elems.iter() {
+                tree.add(n.clone());
+            }
+            let build = now.elapsed();
+
+            let mut rng = rand::thread_rng();
+            let mut total: Duration = Duration::new(0, 0);
+            for _ in 0..ATTEMPTS {
+                let target = RectangleBuilder::new()
+                    .coordinate(
+                        rng.gen_range(0..range) as f32,
+                        rng.gen_range(0..range) as f32,
+                    )
+                    .size(8.0, 8.0)
+                    .build();
+                let now = Instant::now();
+                tree.query(&target);
+                total += now.elapsed();
+            }
+            let stats = tree.stats();
+            println!(
+                "Policy {} ({}): build: {:.3?} query: {:.3?} nodes: {} depth: {}",
+                name,
+                scene,
+                build,
+                total / ATTEMPTS,
+                stats.node_count,
+                stats.max_depth
+            );
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use my_rusted_balls::broadphase::*;
+use my_rusted_balls::objects::*;
+use my_rusted_balls::quadtree::*;
+use rand::Rng;
+
+fn overlaps(a: &QuadBox, b: &QuadBox) -> bool {
+    a.intersects(b) && a.pen_vector(b, &a.minkowski_difference(b)).is_some()
+}
+
+fn check_backend<B: Broadphase<Rectangle>>(mut backend: B) {
+    let elems = my_rusted_balls::gen_vec_of_objects(1000, 1024, 1024, 10, 60, 10, 60);
+    let mut live: Vec<(ObjectId, QuadBox)> = elems
+        .iter()
+        .map(|n| (backend.insert(n.clone()), n.get_box()))
+        .collect();
+
+    let mut rng = rand::thread_rng();
+    for n in live.iter_mut().step_by(2) {
+        let bx = n.1.get_lefttop();
+        n.1 = QuadBox::new(
+            bx.x + rng.gen_range(-50.0..50.0),
+            bx.y + rng.gen_range(-50.0..50.0),
+            n.1.get_size().x,
+            n.1.get_size().y,
+        );
+        assert!(backend.update(n.0, n.1.clone()));
+    }
+    for n in live.split_off(750) {
+        assert!(backend.remove(n.0).is_some());
+        assert!(backend.remove(n.0).is_none());
+        assert!(!backend.update(n.0, n.1));
+    }
+
+    for n in 0..50 {
+        let probe = RectangleBuilder::new()
+            .coordinate(20.0 * n as f32, 1000.0 - 20.0 * n as f32)
+            .size(40.0, 40.0)
+            .build();
+        let mut expected: Vec<ObjectId> = live
+            .iter()
+            .filter(|(_, bx)| overlaps(&probe.get_box(), bx))
+            .map(|(id, _)| *id)
+            .collect();
+        let mut found: Vec<ObjectId> = backend
+            .query(&probe)
+            .iter()
+            .map(|x| x.id.unwrap())
+            .collect();
+        expected.sort();
+        found.sort();
+        assert_eq!(found, expected);
+    }
+
+    let mut expected: Vec<(ObjectId, ObjectId)> = Vec::new();
+    for (i, a) in live.iter().enumerate() {
+        for b in live[i + 1..].iter() {
+            if overlaps(&a.1, &b.1) {
+                expected.push((a.0.min(b.0), a.0.max(b.0)));
+            }
+        }
+    }
+    let mut found: Vec<(ObjectId, ObjectId)> = backend
+        .pairs()
+        .into_iter()
+        .map(|(a, b)| (a.min(b), a.max(b)))
+        .collect();
+    expected.sort();
+    found.sort();
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn quadtree_backend_matches_brute_force() {
+    check_backend(QuadTree::new(1024.0, 1024.0));
+}
+
+#[test]
+fn uniform_grid_backend_matches_brute_force() {
+    check_backend(UniformGrid::new(64.0).unwrap());
+}
+
+#[test]
+fn sweep_and_prune_backend_matches_brute_force() {
+    check_backend(SweepAndPrune::new());
+}
+
+#[test]
+fn grid_reports_straddling_pair_once() {
+    let mut grid = UniformGrid::new(10.0).unwrap();
+    let a = grid.insert(rect(5.0, 5.0, 20.0, 20.0));
+    let b = grid.insert(rect(15.0, 15.0, 20.0, 20.0));
+    assert_eq!(grid.pairs(), vec![(a.min(b), a.max(b))]);
+    assert_eq!(grid.query(&rect(0.0, 0.0, 40.0, 40.0)).len(), 2);
+}
+
+#[test]
+fn grid_pairs_do_not_depend_on_the_instance() {
+    let elems = my_rusted_balls::gen_vec_of_objects(500, 200, 200, 10, 40, 10, 40);
+    let grids: Vec<UniformGrid<Rectangle>> = (0..4)
+        .map(|_| {
+            let mut grid = UniformGrid::new(16.0).unwrap();
+            for n in elems.iter() {
+                grid.insert(n.clone());
+            }
+            grid
+        })
+        .collect();
+    let expected = grids[0].pairs();
+    assert!(expected.len() > 1000);
+    for grid in grids[1..].iter() {
+        assert_eq!(grid.pairs(), expected);
+    }
+}
+
+fn brute_pairs(live: &[(ObjectId, QuadBox)]) -> Vec<(ObjectId, ObjectId)> {
+    let mut ret: Vec<(ObjectId, ObjectId)> = Vec::new();
+    for (i, a) in live.iter().enumerate() {
+        for b in live[i + 1..].iter() {
+            if overlaps(&a.1, &b.1) {
+                ret.push((a.0.min(b.0), a.0.max(b.0)));
+            }
+        }
+    }
+    ret.sort();
+    ret
+}
+
+#[test]
+fn grid_handles_unbounded_boxes() {
+    for size in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+        assert!(UniformGrid::<Rectangle>::new(size).is_none());
+    }
+    let mut grid = UniformGrid::new(1.0).unwrap();
+    let mut live: Vec<(ObjectId, QuadBox)> = Vec::new();
+    for n in [
+        rect(5.0, 5.0, 20.0, 20.0),
+        rect(-1e9, 10.0, 2e9, 5.0),
+        rect(0.0, 0.0, f32::INFINITY, 10.0),
+        rect(f32::NEG_INFINITY, 0.0, f32::INFINITY, f32::INFINITY),
+    ] {
+        live.push((grid.insert(n.clone()), n.get_box()));
+    }
+    let probe = rect(0.0, 0.0, 40.0, 40.0);
+    let mut expected: Vec<ObjectId> = live
+        .iter()
+        .filter(|(_, bx)| overlaps(&probe.get_box(), bx))
+        .map(|(id, _)| *id)
+        .collect();
+    let mut found: Vec<ObjectId> = grid.query(&probe).iter().map(|x| x.id.unwrap()).collect();
+    expected.sort();
+    found.sort();
+    assert_eq!(found, expected);
+    assert!(found.contains(&live[1].0));
+    assert_eq!(grid.pairs(), brute_pairs(&live));
+    assert!(grid.pairs().contains(&(live[0].0, live[1].0)));
+
+    live[1].1 = QuadBox::new(6.0, 6.0, 2.0, 2.0);
+    assert!(grid.update(live[1].0, live[1].1.clone()));
+    live[2].1 = QuadBox::new(-1e9, -1e9, 1.0, 1.0);
+    assert!(grid.update(live[2].0, live[2].1.clone()));
+    assert_eq!(grid.pairs(), brute_pairs(&live));
+    for (id, _) in live {
+        assert!(grid.remove(id).is_some());
+    }
+    assert!(grid.is_empty());
+    assert!(grid.pairs().is_empty());
+}
+
+fn rect(x: f32, y: f32, width: f32, height: f32) -> Rectangle {
+    RectangleBuilder::new()
+        .coordinate(x, y)
+        .size(width, height)
+        .build()
+}
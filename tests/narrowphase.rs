@@ -0,0 +1,110 @@
+use my_rusted_balls::broadphase::*;
+use my_rusted_balls::math::Vec2;
+use my_rusted_balls::narrowphase::*;
+use my_rusted_balls::objects::*;
+use my_rusted_balls::quadtree::*;
+
+fn close(a: Vec2, b: Vec2) -> bool {
+    (a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4
+}
+
+fn circle_box(x: f32, y: f32, radius: f32) -> QuadBox {
+    QuadBox::new(x - radius, y - radius, radius * 2.0, radius * 2.0)
+}
+
+fn ball(x: f32, y: f32, radius: f32) -> Circle {
+    CircleBuilder::new().coordinate(x, y).radius(radius).build()
+}
+
+#[test]
+fn circles_push_apart_along_centres() {
+    let a = circle_box(0.0, 0.0, 10.0);
+    let b = circle_box(12.0, 9.0, 10.0);
+    let pen = penetration(&a, Shape::Circle, &b, Shape::Circle).unwrap();
+    assert!(close(pen, Vec2 { x: -4.0, y: -3.0 }));
+    let back = penetration(&b, Shape::Circle, &a, Shape::Circle).unwrap();
+    assert!(close(back, Vec2 { x: 4.0, y: 3.0 }));
+
+    let apart = circle_box(15.0, 15.0, 10.0);
+    assert!(a.intersects(&apart));
+    assert!(penetration(&a, Shape::Circle, &apart, Shape::Circle).is_none());
+}
+
+#[test]
+fn circle_misses_box_corner() {
+    let wall = QuadBox::new(0.0, 0.0, 100.0, 100.0);
+    let near = circle_box(108.0, 108.0, 10.0);
+    assert!(near.intersects(&wall));
+    assert!(penetration(&near, Shape::Circle, &wall, Shape::Aabb).is_none());
+    assert!(penetration(&wall, Shape::Aabb, &near, Shape::Circle).is_none());
+}
+
+#[test]
+fn circle_hits_box_corner_diagonally() {
+    let wall = QuadBox::new(0.0, 0.0, 100.0, 100.0);
+    let hit = circle_box(104.0, 103.0, 10.0);
+    let pen = penetration(&hit, Shape::Circle, &wall, Shape::Aabb).unwrap();
+    assert!(close(pen, Vec2 { x: 4.0, y: 3.0 }));
+    let back = penetration(&wall, Shape::Aabb, &hit, Shape::Circle).unwrap();
+    assert!(close(back, -pen));
+
+    let side = circle_box(50.0, 106.0, 10.0);
+    let pen = penetration(&side, Shape::Circle, &wall, Shape::Aabb).unwrap();
+    assert!(close(pen, Vec2 { x: 0.0, y: 4.0 }));
+}
+
+#[test]
+fn buried_circle_leaves_through_nearest_face() {
+    let wall = QuadBox::new(0.0, 0.0, 100.0, 100.0);
+    let buried = circle_box(95.0, 40.0, 10.0);
+    let pen = penetration(&buried, Shape::Circle, &wall, Shape::Aabb).unwrap();
+    assert!(close(pen, Vec2 { x: 15.0, y: 0.0 }));
+}
+
+#[test]
+fn boxes_keep_axis_aligned_penetration() {
+    let a = QuadBox::new(0.0, 0.0, 40.0, 40.0);
+    let b = QuadBox::new(30.0, 10.0, 40.0, 40.0);
+    let pen = penetration(&a, Shape::Aabb, &b, Shape::Aabb);
+    assert_eq!(pen, a.pen_vector(&b, &a.minkowski_difference(&b)));
+    assert!(close(pen.unwrap(), Vec2 { x: -10.0, y: 0.0 }));
+}
+
+fn check_backend<B: Broadphase<Box<dyn Bounded>>>(mut backend: B) {
+    let wall: Box<dyn Bounded> = Box::new(
+        RectangleBuilder::new()
+            .coordinate(100.0, 100.0)
+            .size(100.0, 100.0)
+            .build(),
+    );
+    let id = backend.insert(wall);
+    let other = backend.insert(Box::new(ball(400.0, 400.0, 20.0)));
+
+    assert!(backend.query(&ball(208.0, 208.0, 10.0)).is_empty());
+    let found = backend.query(&ball(204.0, 203.0, 10.0));
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, Some(id));
+    assert!(close(found[0].normal, Vec2 { x: 0.8, y: 0.6 }));
+    assert!((found[0].depth - 5.0).abs() < 1e-4);
+
+    assert!(backend.query(&ball(430.0, 430.0, 20.0)).is_empty());
+    let found = backend.query(&ball(430.0, 400.0, 20.0));
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, Some(other));
+    assert!((found[0].depth - 10.0).abs() < 1e-4);
+}
+
+#[test]
+fn quadtree_query_is_shape_aware() {
+    check_backend(QuadTree::new(1024.0, 1024.0));
+}
+
+#[test]
+fn uniform_grid_query_is_shape_aware() {
+    check_backend(UniformGrid::new(64.0).unwrap());
+}
+
+#[test]
+fn sweep_and_prune_query_is_shape_aware() {
+    check_backend(SweepAndPrune::new());
+}
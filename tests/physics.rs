@@ -0,0 +1,400 @@
+use my_rusted_balls::broadphase::*;
+use my_rusted_balls::math::Vec2;
+use my_rusted_balls::objects::*;
+use my_rusted_balls::physics::*;
+use my_rusted_balls::quadtree::*;
+
+const PERIOD: f64 = 1.0 / 64.0;
+
+fn world() -> QuadTree<Rectangle> {
+    QuadTree::new(1024.0, 1024.0)
+}
+
+#[allow(clippy::vec_box)]
+fn scene<B: Broadphase<Rectangle>>(mut world: B) -> (B, Vec<Box<Circle>>) {
+    for n in 0..20 {
+        world.insert(
+            RectangleBuilder::new()
+                .coordinate(50.0 * n as f32, 600.0 + 10.0 * (n % 3) as f32)
+                .size(40.0, 20.0)
+                .build(),
+        );
+    }
+    let balls = (0..4)
+        .map(|n| {
+            Box::new(
+                CircleBuilder::new()
+                    .coordinate(100.0 + 200.0 * n as f32, 100.0)
+                    .radius(10.0)
+                    .speed(Vec2 { x: 150.0, y: 0.0 })
+                    .acel(Vec2 { x: 0.0, y: 2000.0 })
+                    .build(),
+            )
+        })
+        .collect();
+    (world, balls)
+}
+
+fn model<B>(world: B) -> PhysicsModel<BaseMovementModel, BaseCollisionModel, B, ManualClock> {
+    PhysicsModel::with_clock(
+        BaseMovementModel {},
+        BaseCollisionModel {},
+        world,
+        1024.0,
+        1024.0,
+        PERIOD,
+        ManualClock::new(),
+    )
+}
+
+fn positions(balls: &[Box<Circle>]) -> Vec<(f32, f32)> {
+    balls
+        .iter()
+        .map(|x| (x.get_coordinate().x, x.get_coordinate().y))
+        .collect()
+}
+
+#[test]
+fn manual_clock_runs_are_deterministic() {
+    let mut results = Vec::new();
+    for _ in 0..2 {
+        let (tree, mut balls) = scene(world());
+        let mut phy = model(tree);
+        for n in 0..300 {
+            phy.get_clock().advance(PERIOD * (1 + n % 3) as f64);
+            phy.run(&mut balls);
+        }
+        results.push(positions(&balls));
+    }
+    assert_eq!(results[0], results[1]);
+}
+
+#[test]
+fn slow_frame_runs_several_fixed_steps() {
+    let (tree, mut balls) = scene(world());
+    let mut phy = model(tree);
+    assert_eq!(phy.run(&mut balls), 0);
+
+    phy.get_clock().advance(PERIOD / 2.0);
+    assert_eq!(phy.run(&mut balls), 0);
+    assert_eq!(phy.alpha(), 0.5);
+
+    phy.get_clock().advance(PERIOD * 3.0);
+    assert_eq!(phy.run(&mut balls), 3);
+    assert_eq!(phy.alpha(), 0.5);
+
+    let (tree, mut stepped) = scene(world());
+    let mut reference = model(tree);
+    for _ in 0..3 {
+        reference.step(&mut stepped, PERIOD as f32);
+    }
+    assert_eq!(positions(&balls), positions(&stepped));
+}
+
+#[test]
+fn non_positive_period_is_clamped() {
+    for period in [0.0, -1.0, f64::NAN] {
+        let (tree, mut balls) = scene(world());
+        let mut phy = PhysicsModel::with_clock(
+            BaseMovementModel {},
+            BaseCollisionModel {},
+            tree,
+            1024.0,
+            1024.0,
+            period,
+            ManualClock::new(),
+        );
+        assert_eq!(phy.run(&mut balls), 0);
+        assert_eq!(phy.alpha(), 0.0);
+
+        phy.get_clock().advance(PERIOD);
+        assert_eq!(phy.run(&mut balls), 8);
+        assert!((0.0..=1.0).contains(&phy.alpha()));
+        assert!(positions(&balls)
+            .iter()
+            .all(|x| x.0.is_finite() && x.1.is_finite()));
+    }
+}
+
+#[test]
+fn backends_play_out_the_same_scene() {
+    fn play<B: Broadphase<Rectangle>>(world: B) -> Vec<(f32, f32)> {
+        let (world, mut balls) = scene(world);
+        let mut phy = model(world);
+        for _ in 0..300 {
+            phy.step(&mut balls, PERIOD as f32);
+        }
+        positions(&balls)
+    }
+    let expected = play(world());
+    assert_eq!(play(UniformGrid::new(64.0).unwrap()), expected);
+    assert_eq!(play(SweepAndPrune::new()), expected);
+}
+
+#[test]
+fn stall_is_capped_at_max_substeps() {
+    let (tree, mut balls) = scene(world());
+    let mut phy = model(tree);
+    phy.set_max_substeps(4);
+    phy.get_clock().advance(PERIOD * 100.0);
+    assert_eq!(phy.run(&mut balls), 4);
+    assert!(phy.alpha() <= 1.0);
+    phy.get_clock().advance(PERIOD / 4.0);
+    assert!(phy.run(&mut balls) <= 2);
+}
+
+const GRAVITY: f32 = 1000.0;
+
+// Mirrors the ball back above the floor and takes the matching speed off,
+// so the bounce itself neither adds nor removes energy.
+struct ElasticFloor;
+
+impl CollisionModel for ElasticFloor {
+    fn process_collision(
+        &mut self,
+        object: &mut Box<impl MovingObject>,
+        contacts: &[Contact],
+        _time_delta: f32,
+    ) {
+        for contact in contacts.iter() {
+            let depth = -contact.penetration.y;
+            let speed = object.get_speed();
+            if depth <= 0.0 || speed.y <= 0.0 {
+                continue;
+            }
+            object.update_coordinate(Vec2 {
+                x: 0.0,
+                y: -2.0 * depth,
+            });
+            object.set_speed(Vec2 {
+                x: speed.x,
+                y: -(speed.y * speed.y - 4.0 * GRAVITY * depth).max(0.0).sqrt(),
+            });
+        }
+    }
+
+    fn process_pair(
+        &mut self,
+        _object: &mut Box<impl MovingObject>,
+        _other: &mut Box<impl MovingObject>,
+        _contact: &Contact,
+        _time_delta: f32,
+    ) {
+    }
+}
+
+fn energy_drift(m_model: impl MovementModel) -> f32 {
+    const HEIGHT: f32 = 1024.0;
+    let mut balls = vec![Box::new(
+        CircleBuilder::new()
+            .coordinate(500.0, 300.0)
+            .radius(10.0)
+            .acel(Vec2 { x: 0.0, y: GRAVITY })
+            .build(),
+    )];
+    let energy = |x: &Circle| {
+        let speed = x.get_speed();
+        speed.length_sqr() / 2.0 + GRAVITY * (HEIGHT - x.get_coordinate().y)
+    };
+    let start = energy(&balls[0]);
+    let mut phy = PhysicsModel::with_clock(
+        m_model,
+        ElasticFloor {},
+        world(),
+        1024.0,
+        HEIGHT,
+        PERIOD,
+        ManualClock::new(),
+    );
+    let mut drift: f32 = 0.0;
+    for _ in 0..1000 {
+        phy.step(&mut balls, PERIOD as f32);
+        drift = drift.max((energy(&balls[0]) - start).abs() / start);
+    }
+    drift
+}
+
+#[test]
+fn integrators_energy_drift() {
+    let euler = energy_drift(SymplecticEuler {});
+    let velocity_verlet = energy_drift(VelocityVerlet {});
+    let position_verlet = energy_drift(PositionVerlet {});
+    let rk4 = energy_drift(Rk4 {});
+    println!(
+        "Drift euler: {:.2e} velocity verlet: {:.2e} position verlet: {:.2e} rk4: {:.2e}",
+        euler, velocity_verlet, position_verlet, rk4
+    );
+    assert!(euler < 0.5);
+    for drift in [velocity_verlet, position_verlet, rk4] {
+        assert!(drift < 1e-4);
+        assert!(drift * 100.0 < euler);
+    }
+}
+
+fn floor_hit(body: BodyProperties, speed: Vec2) -> Vec2 {
+    let mut ball = Box::new(
+        CircleBuilder::new()
+            .coordinate(100.0, 1000.0)
+            .radius(10.0)
+            .speed(speed)
+            .body(body)
+            .build(),
+    );
+    let floor = QuadBox::new(0.0, 1005.0, 1024.0, 100.0);
+    let contact = Contact::new(None, floor, Vec2 { x: 0.0, y: -5.0 });
+    BaseCollisionModel {}.process_collision(&mut ball, &[contact], PERIOD as f32);
+    assert_eq!(ball.get_coordinate().y, 985.0);
+    ball.get_speed()
+}
+
+#[test]
+fn impulse_respects_restitution_and_friction() {
+    let elastic = floor_hit(BodyProperties::default(), Vec2 { x: 50.0, y: 100.0 });
+    assert_eq!((elastic.x, elastic.y), (50.0, -100.0));
+
+    let rubber = floor_hit(BodyProperties::rubber(), Vec2 { x: 50.0, y: 100.0 });
+    assert!((rubber.y + 80.0).abs() < 1e-3);
+    assert!(rubber.x.abs() < 1e-3);
+
+    let ice = floor_hit(BodyProperties::ice(), Vec2 { x: 50.0, y: 100.0 });
+    assert!((ice.y + 30.0).abs() < 1e-3);
+    assert!((ice.x - (50.0 - 0.02 * 130.0)).abs() < 1e-3);
+
+    let leaving = floor_hit(BodyProperties::steel(), Vec2 { x: 50.0, y: -100.0 });
+    assert_eq!((leaving.x, leaving.y), (50.0, -100.0));
+}
+
+fn bounce_heights(body: BodyProperties) -> Vec<f32> {
+    let mut balls = vec![Box::new(
+        CircleBuilder::new()
+            .coordinate(500.0, 300.0)
+            .radius(10.0)
+            .acel(Vec2 { x: 0.0, y: GRAVITY })
+            .body(body)
+            .build(),
+    )];
+    let mut phy = model(world());
+    let mut heights = Vec::new();
+    let mut top = f32::MAX;
+    for _ in 0..3000 {
+        let rising = balls[0].get_speed().y < 0.0;
+        phy.step(&mut balls, PERIOD as f32 / 2.0);
+        top = top.min(balls[0].get_coordinate().y);
+        if rising && balls[0].get_speed().y >= 0.0 {
+            heights.push(1024.0 - top);
+            top = f32::MAX;
+        }
+    }
+    heights
+}
+
+#[test]
+fn bounces_lose_height_with_restitution() {
+    let elastic = bounce_heights(BodyProperties::default());
+    assert!(elastic.len() > 3);
+    assert!(elastic.iter().all(|x| *x > 0.95 * elastic[0]));
+
+    let rubber = bounce_heights(BodyProperties::rubber());
+    assert!(rubber.len() > 3);
+    assert!(rubber[..4].windows(2).all(|x| x[1] < x[0]));
+    assert!(rubber[3] < 0.5 * rubber[0]);
+    assert!(*rubber.last().unwrap() < 25.0);
+}
+
+fn ball(x: f32, speed: f32, body: BodyProperties) -> Box<Circle> {
+    Box::new(
+        CircleBuilder::new()
+            .coordinate(x, 500.0)
+            .radius(10.0)
+            .speed(Vec2 { x: speed, y: 0.0 })
+            .body(body)
+            .build(),
+    )
+}
+
+fn momentum(balls: &[Box<Circle>]) -> f32 {
+    balls
+        .iter()
+        .map(|x| x.get_body().mass * x.get_speed().x)
+        .sum()
+}
+
+#[test]
+fn moving_balls_collide_head_on() {
+    let mut balls = vec![
+        ball(100.0, 100.0, BodyProperties::default()),
+        ball(200.0, -100.0, BodyProperties::default()),
+    ];
+    let mut phy = model(world());
+    for _ in 0..64 {
+        phy.step(&mut balls, PERIOD as f32);
+        assert!(balls[0].get_coordinate().x < balls[1].get_coordinate().x);
+        assert!(momentum(&balls).abs() < 1e-3);
+    }
+    assert!((balls[0].get_speed().x + 100.0).abs() < 1e-3);
+    assert!((balls[1].get_speed().x - 100.0).abs() < 1e-3);
+}
+
+#[allow(clippy::vec_box)]
+fn mixed_balls() -> Vec<Box<Circle>> {
+    vec![
+        ball(100.0, 200.0, BodyProperties::steel()),
+        ball(200.0, 0.0, BodyProperties::rubber()),
+        ball(900.0, 0.0, BodyProperties::ice()),
+    ]
+}
+
+#[test]
+fn unequal_masses_conserve_momentum() {
+    let mut balls = mixed_balls();
+    let before = momentum(&balls);
+    let mut phy = model(world());
+    for _ in 0..64 {
+        phy.step(&mut balls, PERIOD as f32);
+        assert!((momentum(&balls) - before).abs() < 1e-2 * before);
+    }
+    let restitution = BodyProperties::steel().restitution;
+    let separation = balls[1].get_speed().x - balls[0].get_speed().x;
+    assert!((separation - restitution * 200.0).abs() < 1e-2);
+    assert_eq!(balls[2].get_speed().x, 0.0);
+}
+
+#[allow(clippy::vec_box)]
+fn gas() -> Vec<Box<Circle>> {
+    (0..100)
+        .map(|n| {
+            Box::new(
+                CircleBuilder::new()
+                    .coordinate(
+                        100.0 + 80.0 * (n % 10) as f32,
+                        100.0 + 80.0 * (n / 10) as f32,
+                    )
+                    .radius(30.0)
+                    .speed(Vec2 {
+                        x: 100.0 * ((n * 7 % 11) as f32 - 5.0),
+                        y: 100.0 * ((n * 5 % 13) as f32 - 6.0),
+                    })
+                    .build(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn body_broadphase_is_pluggable() {
+    fn play<D: Broadphase<BodyProxy>>(bodies: D) -> Vec<(f32, f32)> {
+        let mut balls = gas();
+        let mut phy = model(world()).with_bodies(bodies);
+        for _ in 0..200 {
+            phy.step(&mut balls, PERIOD as f32);
+        }
+        positions(&balls)
+    }
+    let sweep = play(SweepAndPrune::new());
+    assert_ne!(sweep, positions(&gas()));
+    assert_eq!(play(SweepAndPrune::new()), sweep);
+    let grid = play(UniformGrid::new(32.0).unwrap());
+    assert_eq!(play(UniformGrid::new(32.0).unwrap()), grid);
+    let tree = play(QuadTree::new(1024.0, 1024.0));
+    assert_eq!(play(QuadTree::new(1024.0, 1024.0)), tree);
+}
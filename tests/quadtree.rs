@@ -0,0 +1,770 @@
+use my_rusted_balls::math::Vec2;
+use my_rusted_balls::objects::*;
+use my_rusted_balls::quadtree::*;
+use std::ops::ControlFlow;
+
+fn rect(x: f32, y: f32, width: f32, height: f32) -> Rectangle {
+    RectangleBuilder::new()
+        .coordinate(x, y)
+        .size(width, height)
+        .build()
+}
+
+fn fill_tree(tree: &mut QuadTree<Rectangle>, num: u32) -> Vec<ObjectId> {
+    let elems = my_rusted_balls::gen_vec_of_objects(num, 1024, 1024, 10, 100, 10, 100);
+    elems.iter().map(|n| tree.add(n.clone())).collect()
+}
+
+#[test]
+fn add_returns_unique_ids() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let ids = fill_tree(&mut tree, 1000);
+    let mut sorted = ids.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted.len(), ids.len());
+    assert_eq!(tree.len(), 1000);
+}
+
+#[test]
+fn get_finds_every_element() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let elems = my_rusted_balls::gen_vec_of_objects(1000, 1024, 1024, 10, 100, 10, 100);
+    let ids: Vec<ObjectId> = elems.iter().map(|n| tree.add(n.clone())).collect();
+    for (id, elem) in ids.iter().zip(elems.iter()) {
+        let found = tree.get(*id).expect("element is missing");
+        assert_eq!(found.to_string(), elem.to_string());
+    }
+}
+
+#[test]
+fn remove_deletes_only_target() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let ids = fill_tree(&mut tree, 1000);
+    for id in ids.iter().step_by(2) {
+        assert!(tree.remove(*id).is_some());
+    }
+    for (n, id) in ids.iter().enumerate() {
+        assert_eq!(tree.get(*id).is_some(), n % 2 == 1);
+    }
+    assert!(tree.remove(ids[0]).is_none());
+    assert_eq!(tree.len(), 500);
+}
+
+#[test]
+fn removed_element_is_not_queried() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let id = tree.add(rect(100.0, 100.0, 50.0, 50.0));
+    let probe = Box::new(
+        RectangleBuilder::new()
+            .coordinate(120.0, 120.0)
+            .size(10.0, 10.0)
+            .build(),
+    );
+    assert_eq!(tree.query(&probe).len(), 1);
+    tree.remove(id);
+    assert!(tree.query(&probe).is_empty());
+}
+
+#[test]
+fn get_mut_returns_stored_object() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    fill_tree(&mut tree, 100);
+    let id = tree.add(rect(10.0, 10.0, 20.0, 20.0));
+    let obj = tree.get_mut(id).unwrap();
+    assert_eq!(obj.get_box().get_size().x, 20.0);
+}
+
+#[test]
+fn update_refiles_moved_element() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    fill_tree(&mut tree, 1000);
+    let old_probe = Box::new(rect_probe(105.0, 105.0));
+    let new_probe = Box::new(rect_probe(905.0, 905.0));
+    let old_before = tree.query(&old_probe).len();
+    let new_before = tree.query(&new_probe).len();
+    let id = tree.add(rect(100.0, 100.0, 20.0, 20.0));
+    assert_eq!(tree.query(&old_probe).len(), old_before + 1);
+
+    assert!(tree.update(id, QuadBox::new(900.0, 900.0, 20.0, 20.0)));
+    assert_eq!(tree.query(&old_probe).len(), old_before);
+    assert_eq!(tree.query(&new_probe).len(), new_before + 1);
+
+    assert!(tree.update(id, QuadBox::new(901.0, 901.0, 20.0, 20.0)));
+    assert_eq!(tree.query(&new_probe).len(), new_before + 1);
+    assert!(tree.get(id).is_some());
+    assert!(tree.remove(id).is_some());
+    assert_eq!(tree.query(&new_probe).len(), new_before);
+    assert!(!tree.update(id, QuadBox::new(0.0, 0.0, 1.0, 1.0)));
+}
+
+fn rect_probe(x: f32, y: f32) -> Rectangle {
+    RectangleBuilder::new()
+        .coordinate(x, y)
+        .size(5.0, 5.0)
+        .build()
+}
+
+#[test]
+fn removing_everything_collapses_tree() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let ids = fill_tree(&mut tree, 1000);
+    assert!(tree.get_boxes().len() > 1);
+    for id in ids.iter() {
+        tree.remove(*id);
+    }
+    assert_eq!(tree.get_boxes().len(), 1);
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn compact_merges_sparse_children() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    for n in 0..5 {
+        tree.add(rect(500.0 + n as f32, 500.0, 30.0, 30.0));
+    }
+    tree.add(rect(10.0, 10.0, 10.0, 10.0));
+    assert_eq!(tree.get_boxes().len(), 5);
+    tree.compact();
+    assert_eq!(tree.get_boxes().len(), 1);
+    assert_eq!(tree.len(), 6);
+}
+
+#[test]
+fn query_reports_hit_object() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let id = tree.add(rect(100.0, 100.0, 50.0, 50.0));
+    tree.add(rect(150.0, 100.0, 50.0, 50.0));
+    let probe = Box::new(
+        RectangleBuilder::new()
+            .coordinate(90.0, 120.0)
+            .size(20.0, 10.0)
+            .build(),
+    );
+    let contacts = tree.query(&probe);
+    assert_eq!(contacts.len(), 1);
+    assert_eq!(contacts[0].id, Some(id));
+    assert_eq!(
+        contacts[0].other_box,
+        QuadBox::new(100.0, 100.0, 50.0, 50.0)
+    );
+    assert_eq!(contacts[0].penetration.x, -10.0);
+    assert_eq!(contacts[0].normal.x, -1.0);
+    assert_eq!(contacts[0].depth, 10.0);
+
+    let touching = Box::new(
+        RectangleBuilder::new()
+            .coordinate(80.0, 120.0)
+            .size(20.0, 10.0)
+            .build(),
+    );
+    assert!(tree.query(&touching).is_empty());
+}
+
+#[test]
+fn raycast_returns_nearest_hit() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let far = tree.add(rect(600.0, 90.0, 40.0, 40.0));
+    let near = tree.add(rect(300.0, 80.0, 40.0, 40.0));
+    tree.add(rect(300.0, 600.0, 40.0, 40.0));
+    for n in 0..20 {
+        tree.add(rect(20.0 * n as f32, 900.0, 10.0, 10.0));
+    }
+    let origin = Vec2 { x: 10.0, y: 100.0 };
+    let dir = Vec2 { x: 1.0, y: 0.0 };
+
+    let hit = tree.raycast(origin, dir, 1000.0).unwrap();
+    assert_eq!(hit.id, near);
+    assert_eq!(hit.distance, 290.0);
+    assert_eq!(hit.point, Vec2 { x: 300.0, y: 100.0 });
+    assert_eq!(hit.normal, Vec2 { x: -1.0, y: 0.0 });
+    assert!(tree.raycast(origin, dir, 200.0).is_none());
+
+    let hits = tree.all_hits(origin, dir, 1000.0);
+    let ids: Vec<ObjectId> = hits.iter().map(|x| x.id).collect();
+    assert_eq!(ids, vec![near, far]);
+
+    let hit = tree
+        .segment_cast(Vec2 { x: 620.0, y: 10.0 }, Vec2 { x: 620.0, y: 500.0 })
+        .unwrap();
+    assert_eq!(hit.id, far);
+    assert_eq!(hit.normal, Vec2 { x: 0.0, y: -1.0 });
+    assert_eq!(hit.distance, 80.0);
+    assert!(tree
+        .segment_all_hits(Vec2 { x: 10.0, y: 10.0 }, Vec2 { x: 200.0, y: 10.0 })
+        .is_empty());
+}
+
+#[test]
+fn k_nearest_matches_brute_force() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let elems = my_rusted_balls::gen_vec_of_objects(2000, 1024, 1024, 5, 20, 5, 20);
+    let ids: Vec<ObjectId> = elems.iter().map(|n| tree.add(n.clone())).collect();
+    let point = Vec2 { x: 400.0, y: 700.0 };
+
+    let mut expected: Vec<f32> = elems
+        .iter()
+        .map(|n| n.get_box().distance_to_point(point))
+        .collect();
+    expected.sort_by(|a, b| a.total_cmp(b));
+
+    let found = tree.k_nearest(point, 10, f32::INFINITY);
+    let distances: Vec<f32> = found.iter().map(|x| x.distance).collect();
+    assert_eq!(distances, expected[..10].to_vec());
+    for n in found.iter() {
+        let idx = ids.iter().position(|x| *x == n.id).unwrap();
+        assert_eq!(elems[idx].get_box().distance_to_point(point), n.distance);
+    }
+
+    let nearest = tree.nearest(point).unwrap();
+    assert_eq!(nearest.distance, expected[0]);
+
+    let radius = expected[4];
+    let within = tree.k_nearest(point, 100, radius);
+    assert_eq!(
+        within.len(),
+        expected.iter().filter(|x| **x <= radius).count()
+    );
+    assert!(QuadTree::<Rectangle>::new(10.0, 10.0)
+        .nearest(point)
+        .is_none());
+}
+
+#[test]
+fn visitor_queries_match_brute_force() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let elems = my_rusted_balls::gen_vec_of_objects(2000, 1024, 1024, 10, 100, 10, 100);
+    for n in elems.iter() {
+        tree.add(n.clone());
+    }
+    let point = Vec2 { x: 300.0, y: 500.0 };
+    let region = QuadBox::new(200.0, 200.0, 150.0, 80.0);
+
+    let mut count = 0;
+    let _ = tree.query_point(point, |_| {
+        count += 1;
+        ControlFlow::Continue(())
+    });
+    let expected = elems
+        .iter()
+        .filter(|n| n.get_box().contains_point(point))
+        .count();
+    assert_eq!(count, expected);
+
+    let mut count = 0;
+    let _ = tree.query_box(&region, |_| {
+        count += 1;
+        ControlFlow::Continue(())
+    });
+    let expected = elems
+        .iter()
+        .filter(|n| n.get_box().intersects(&region))
+        .count();
+    assert_eq!(count, expected);
+
+    let mut count = 0;
+    let _ = tree.query_circle(point, 40.0, |x| {
+        assert!(x.get_box().distance_to_point(point) <= 40.0);
+        count += 1;
+        ControlFlow::Continue(())
+    });
+    let expected = elems
+        .iter()
+        .filter(|n| n.get_box().distance_to_point(point) <= 40.0)
+        .count();
+    assert_eq!(count, expected);
+
+    let mut count = 0;
+    let flow = tree.query_box(&QuadBox::new(0.0, 0.0, 1024.0, 1024.0), |_| {
+        count += 1;
+        if count == 3 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    assert_eq!(flow, ControlFlow::Break(()));
+    assert_eq!(count, 3);
+}
+
+struct Entity {
+    key: u32,
+    bounds: QuadBox,
+}
+
+impl Bounded for Entity {
+    fn get_box(&self) -> QuadBox {
+        self.bounds.clone()
+    }
+}
+
+#[test]
+fn indexes_plain_structs() {
+    let mut tree: QuadTree<Entity> = QuadTree::new(1024.0, 1024.0);
+    for n in 0..100 {
+        tree.add(Entity {
+            key: n,
+            bounds: QuadBox::new(10.0 * n as f32, 10.0 * n as f32, 5.0, 5.0),
+        });
+    }
+    let mut keys: Vec<u32> = Vec::new();
+    let _ = tree.query_box(&QuadBox::new(0.0, 0.0, 100.0, 100.0), |x| {
+        keys.push(x.key);
+        ControlFlow::Continue(())
+    });
+    keys.sort();
+    assert_eq!(keys, (0..11).collect::<Vec<u32>>());
+}
+
+#[test]
+fn loose_tree_matches_tight_tree() {
+    let mut tight = QuadTree::new(1024.0, 1024.0);
+    let mut loose = QuadTree::new_loose(1024.0, 1024.0, 2.0);
+    let elems = my_rusted_balls::gen_vec_of_objects(3000, 1024, 1024, 10, 100, 10, 100);
+    let mut ids: Vec<ObjectId> = Vec::new();
+    for n in elems.iter() {
+        let id = tight.add(n.clone());
+        assert_eq!(loose.add(n.clone()), id);
+        ids.push(id);
+    }
+    for id in ids.iter().step_by(3) {
+        assert!(tight.remove(*id).is_some());
+        assert!(loose.remove(*id).is_some());
+    }
+
+    for n in 0..50 {
+        let probe = rect_probe(20.0 * n as f32, 1000.0 - 20.0 * n as f32);
+        let mut expected: Vec<ObjectId> = tight.query(&probe).iter().filter_map(|x| x.id).collect();
+        let mut found: Vec<ObjectId> = loose.query(&probe).iter().filter_map(|x| x.id).collect();
+        expected.sort();
+        found.sort();
+        assert_eq!(found, expected);
+
+        let point = Vec2 {
+            x: 20.0 * n as f32,
+            y: 10.0 * n as f32,
+        };
+        assert_eq!(
+            loose.nearest(point).map(|x| x.distance),
+            tight.nearest(point).map(|x| x.distance)
+        );
+        let dir = Vec2 { x: 1.0, y: 0.5 };
+        assert_eq!(
+            loose.raycast(point, dir, 2000.0).map(|x| x.distance),
+            tight.raycast(point, dir, 2000.0).map(|x| x.distance)
+        );
+    }
+}
+
+#[test]
+fn tree_grows_to_fit_outside_elements() {
+    let mut tree = QuadTree::new(100.0, 100.0);
+    let mut elems: Vec<(ObjectId, Vec2)> = Vec::new();
+    for n in 0..200 {
+        let x = -1000.0 + 13.0 * n as f32;
+        let y = 700.0 - 9.0 * n as f32;
+        elems.push((tree.add(rect(x, y, 5.0, 5.0)), Vec2 { x, y }));
+    }
+    let root = tree.get_boxes()[0].clone().unwrap();
+    let corner = root.get_lefttop();
+    assert!(corner.x <= -1000.0 && corner.y <= 700.0 - 9.0 * 199.0);
+    assert!(root.get_right_x() >= -1000.0 + 13.0 * 199.0 + 5.0);
+    assert!(tree.get_boxes().len() > 1);
+
+    for (id, point) in elems.iter() {
+        let ids: Vec<ObjectId> = tree
+            .query(&rect_probe(point.x, point.y))
+            .iter()
+            .filter_map(|x| x.id)
+            .collect();
+        assert!(ids.contains(id));
+    }
+
+    let (id, _) = elems[0];
+    assert!(tree.update(id, QuadBox::new(5000.0, -5000.0, 5.0, 5.0)));
+    let ids: Vec<ObjectId> = tree
+        .query(&rect_probe(5000.0, -5000.0))
+        .iter()
+        .filter_map(|x| x.id)
+        .collect();
+    assert_eq!(ids, vec![id]);
+
+    for (id, _) in elems.iter() {
+        assert!(tree.remove(*id).is_some());
+    }
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn remove_after_growing_past_edge_element() {
+    for looseness in [1.0, 2.0] {
+        for (edge, outside) in [
+            (rect(0.0, 50.0, 0.0, 10.0), rect(-10.0, 50.0, 5.0, 5.0)),
+            (rect(50.0, 0.0, 10.0, 0.0), rect(50.0, -10.0, 5.0, 5.0)),
+        ] {
+            let mut tree = QuadTree::new_loose(100.0, 100.0, looseness);
+            let edge_box = edge.get_box();
+            let first = tree.add(edge);
+            let second = tree.add(outside);
+            assert!(tree.remove(first).is_some());
+            assert_eq!(tree.len(), 1);
+            assert_eq!(tree.iter().count(), 1);
+            let probe = QuadBox::new(
+                edge_box.get_lefttop().x - 1.0,
+                edge_box.get_lefttop().y - 1.0,
+                2.0,
+                2.0,
+            );
+            assert!(tree.query(&probe).iter().all(|x| x.id == Some(second)));
+            assert!(tree.remove(second).is_some());
+            assert!(tree.is_empty());
+        }
+    }
+}
+
+#[test]
+fn overlapping_pairs_match_brute_force() {
+    let elems = my_rusted_balls::gen_vec_of_objects(1000, 1024, 1024, 10, 100, 10, 100);
+    let mut expected: Vec<(u32, u32)> = Vec::new();
+    for (i, a) in elems.iter().enumerate() {
+        for (j, b) in elems.iter().enumerate().skip(i + 1) {
+            let (a, b) = (a.get_box(), b.get_box());
+            if a.intersects(&b) && a.pen_vector(&b, &a.minkowski_difference(&b)).is_some() {
+                expected.push((i as u32, j as u32));
+            }
+        }
+    }
+
+    for looseness in [1.0, 2.0] {
+        let mut tree = QuadTree::new_loose(1024.0, 1024.0, looseness);
+        let ids: Vec<ObjectId> = elems.iter().map(|n| tree.add(n.clone())).collect();
+        let mut found: Vec<(u32, u32)> = tree
+            .overlapping_pairs()
+            .map(|(a, b)| {
+                let a = ids.iter().position(|x| *x == a).unwrap() as u32;
+                let b = ids.iter().position(|x| *x == b).unwrap() as u32;
+                (a.min(b), a.max(b))
+            })
+            .collect();
+        found.sort();
+        assert_eq!(found, expected);
+    }
+}
+
+#[test]
+fn from_slice_matches_incremental_build() {
+    let elems = my_rusted_balls::gen_vec_of_objects(20000, 1024, 1024, 10, 100, 10, 100);
+    let mut incremental = QuadTree::new(1024.0, 1024.0);
+    for n in elems.iter() {
+        incremental.add(n.clone());
+    }
+    let mut bulk = QuadTree::from_slice(&elems);
+    assert_eq!(bulk.len(), elems.len());
+
+    for n in 0..50 {
+        let probe = rect_probe(20.0 * n as f32, 1000.0 - 20.0 * n as f32);
+        let mut expected: Vec<ObjectId> = incremental
+            .query(&probe)
+            .iter()
+            .filter_map(|x| x.id)
+            .collect();
+        let mut found: Vec<ObjectId> = bulk.query(&probe).iter().filter_map(|x| x.id).collect();
+        expected.sort();
+        found.sort();
+        assert_eq!(found, expected);
+    }
+
+    let ids: Vec<ObjectId> = bulk.overlapping_pairs().map(|(a, _)| a).collect();
+    for id in ids.iter() {
+        bulk.remove(*id);
+    }
+    let id = bulk.add(rect(-50.0, -50.0, 10.0, 10.0));
+    let found: Vec<ObjectId> = bulk
+        .query(&rect_probe(-48.0, -48.0))
+        .iter()
+        .filter_map(|x| x.id)
+        .collect();
+    assert_eq!(found, vec![id]);
+}
+
+#[test]
+fn stats_describe_tree_shape() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    tree.print();
+    let stats = tree.stats();
+    assert_eq!(stats.node_count, 1);
+    assert_eq!(stats.leaf_count, 1);
+    assert_eq!(stats.max_depth, 0);
+    assert_eq!(stats.elems_per_depth, vec![0]);
+    assert_eq!(stats.avg_elems_per_leaf, 0.0);
+
+    for n in 0..3 {
+        tree.add(rect(100.0 * n as f32, 100.0, 20.0, 20.0));
+    }
+    assert_eq!(tree.stats().node_count, 1);
+    assert_eq!(tree.stats().root_straddlers, 0);
+
+    fill_tree(&mut tree, 4997);
+    let stats = tree.stats();
+    assert_eq!(stats.node_count, tree.get_boxes().len());
+    assert_eq!(stats.node_count % 4, 1);
+    assert!(stats.leaf_count < stats.node_count);
+    assert_eq!(stats.elems_per_depth.len(), stats.max_depth as usize + 1);
+    assert_eq!(stats.elems_per_depth.iter().sum::<usize>(), 5000);
+    assert_eq!(stats.root_straddlers, stats.elems_per_depth[0]);
+    assert!(stats.avg_elems_per_leaf <= 4.0);
+
+    let json = tree.to_json();
+    assert!(json.starts_with("{\"len\":5000,"));
+    assert_eq!(json.matches("\"box\"").count(), stats.node_count);
+    assert_eq!(json.matches('{').count(), json.matches('}').count());
+    assert_eq!(json.matches('[').count(), json.matches(']').count());
+}
+
+struct NamedPolicy(&'static str);
+
+impl std::fmt::Debug for NamedPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl SplitPolicy for NamedPolicy {
+    fn should_split(&self, _node_box: &QuadBox, depth: u32, num_of_elems: usize) -> bool {
+        depth < 8 && num_of_elems > 4
+    }
+}
+
+#[test]
+fn json_dump_is_always_valid() {
+    let mut tree = QuadTreeBuilder::new()
+        .size(1024.0, 1024.0)
+        .looseness(f32::INFINITY)
+        .policy(NamedPolicy("quote \" slash \\ line \n tab \t"))
+        .build();
+    fill_tree(&mut tree, 100);
+    let json: serde_json::Value = serde_json::from_str(&tree.to_json()).unwrap();
+    assert_eq!(json["len"], 100);
+    assert!(json["looseness"].is_null());
+    assert_eq!(json["policy"], "quote \" slash \\ line \n tab \t");
+}
+
+fn policy_trees() -> Vec<QuadTree<Rectangle>> {
+    vec![
+        QuadTreeBuilder::new()
+            .size(1024.0, 1024.0)
+            .policy(CountPolicy::default())
+            .build(),
+        QuadTreeBuilder::new()
+            .size(1024.0, 1024.0)
+            .policy(MinCellSizePolicy::new(32.0, 4).unwrap())
+            .build(),
+        QuadTreeBuilder::new()
+            .size(1024.0, 1024.0)
+            .policy(MedianPolicy::default())
+            .build(),
+        QuadTreeBuilder::new()
+            .size(1024.0, 1024.0)
+            .looseness(2.0)
+            .policy(MedianPolicy::default())
+            .build(),
+    ]
+}
+
+#[test]
+fn split_policies_match_brute_force() {
+    let elems = my_rusted_balls::gen_vec_of_objects(3000, 1024, 1024, 10, 100, 10, 100);
+    for mut tree in policy_trees() {
+        let ids: Vec<ObjectId> = elems.iter().map(|n| tree.add(n.clone())).collect();
+        for id in ids.iter().step_by(2) {
+            assert!(tree.remove(*id).is_some());
+        }
+        tree.compact();
+        for n in 0..50 {
+            let probe = rect_probe(20.0 * n as f32, 1000.0 - 20.0 * n as f32);
+            let target = probe.get_box();
+            let mut expected: Vec<ObjectId> = ids
+                .iter()
+                .enumerate()
+                .skip(1)
+                .step_by(2)
+                .filter(|(i, _)| {
+                    let u_box = elems[*i].get_box();
+                    target.intersects(&u_box)
+                        && target
+                            .pen_vector(&u_box, &target.minkowski_difference(&u_box))
+                            .is_some()
+                })
+                .map(|(_, id)| *id)
+                .collect();
+            let mut found: Vec<ObjectId> = tree.query(&probe).iter().filter_map(|x| x.id).collect();
+            expected.sort();
+            found.sort();
+            assert_eq!(found, expected);
+        }
+    }
+
+    let bulk = QuadTreeBuilder::new()
+        .policy(MedianPolicy::default())
+        .build_from_slice(&elems);
+    let mut incremental = QuadTree::new(1024.0, 1024.0);
+    for n in elems.iter() {
+        incremental.add(n.clone());
+    }
+    let probe = rect_probe(500.0, 500.0);
+    assert_eq!(bulk.query(&probe).len(), incremental.query(&probe).len());
+}
+
+#[test]
+fn split_policies_limit_clustered_depth() {
+    let mut trees = policy_trees();
+    trees.truncate(3);
+    for tree in trees.iter_mut() {
+        for _ in 0..50 {
+            tree.add(rect(300.0, 300.0, 0.01, 0.01));
+        }
+    }
+    let depths: Vec<u32> = trees.iter().map(|x| x.stats().max_depth).collect();
+    assert!(depths[0] >= 15);
+    assert!(depths[1] <= 5);
+    assert!(depths[2] <= 1);
+}
+
+#[test]
+fn min_cell_size_policy_stops_on_coincident_points() {
+    for size in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+        assert!(MinCellSizePolicy::new(size, 4).is_none());
+    }
+    let policy = MinCellSizePolicy::new(f32::MIN_POSITIVE, 4)
+        .unwrap()
+        .max_depth(20);
+    let elems: Vec<Rectangle> = (0..5).map(|_| rect(300.0, 300.0, 0.0, 0.0)).collect();
+
+    let mut tree = QuadTreeBuilder::new()
+        .size(1024.0, 1024.0)
+        .policy(policy.clone())
+        .build();
+    for n in elems.iter() {
+        tree.add(n.clone());
+    }
+    assert_eq!(tree.stats().max_depth, 20);
+
+    let bulk = QuadTreeBuilder::new()
+        .size(1024.0, 1024.0)
+        .policy(policy)
+        .build_from_slice(&elems);
+    assert_eq!(bulk.stats().max_depth, 20);
+    assert_eq!(bulk.len(), 5);
+}
+
+#[test]
+fn iterators_enumerate_contents() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let mut ids: Vec<ObjectId> = (0..100)
+        .map(|n| {
+            tree.add(Entity {
+                key: n,
+                bounds: QuadBox::new(10.0 * n as f32, 10.0 * n as f32, 5.0, 5.0),
+            })
+        })
+        .collect();
+    let mut listed: Vec<ObjectId> = tree.iter().map(|(id, _)| id).collect();
+    listed.sort();
+    ids.sort();
+    assert_eq!(listed, ids);
+
+    for (_, x) in tree.iter_mut() {
+        x.key += 1000;
+    }
+    assert!(tree
+        .iter()
+        .all(|(id, x)| tree.get(id).unwrap().key == x.key && x.key >= 1000));
+
+    let mut keys: Vec<u32> = tree
+        .iter_in_box(&QuadBox::new(100.0, 100.0, 95.0, 95.0))
+        .map(|(_, x)| x.key - 1000)
+        .collect();
+    keys.sort();
+    assert_eq!(keys, (10..20).collect::<Vec<u32>>());
+
+    tree.retain(|_, x| x.key % 2 == 0);
+    assert_eq!(tree.len(), 50);
+    assert!(tree.iter().all(|(_, x)| x.key % 2 == 0));
+    assert_eq!(
+        tree.iter_in_box(&QuadBox::new(100.0, 100.0, 95.0, 95.0))
+            .count(),
+        5
+    );
+
+    let drained: Vec<(ObjectId, Entity)> = tree.drain().collect();
+    assert_eq!(drained.len(), 50);
+    assert!(tree.is_empty());
+    assert_eq!(tree.iter().count(), 0);
+    assert!(tree.get(drained[0].0).is_none());
+    assert_eq!(tree.stats().node_count, 1);
+}
+
+fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+#[test]
+fn shared_tree_serves_threads() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    let elems = my_rusted_balls::gen_vec_of_objects(2000, 1024, 1024, 10, 100, 10, 100);
+    let boxes: Vec<QuadBox> = elems.iter().map(|n| n.get_box()).collect();
+    for n in boxes.iter() {
+        tree.add(n.clone());
+    }
+    assert_send_sync(&tree);
+
+    let tree = &tree;
+    let expected: Vec<usize> = boxes.iter().map(|n| tree.query(n).len()).collect();
+    let found: Vec<usize> = std::thread::scope(|s| {
+        let handles: Vec<std::thread::ScopedJoinHandle<Vec<usize>>> = boxes
+            .chunks(500)
+            .map(|chunk| s.spawn(move || chunk.iter().map(|n| tree.query(n).len()).collect()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect::<Vec<usize>>()
+    });
+    assert_eq!(found, expected);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn par_query_matches_sequential() {
+    let elems = my_rusted_balls::gen_vec_of_objects(2000, 1024, 1024, 10, 100, 10, 100);
+    let boxes: Vec<QuadBox> = elems.iter().map(|n| n.get_box()).collect();
+    let tree = QuadTree::from_slice(&boxes);
+    let found = tree.par_query(&boxes);
+    for (n, contacts) in boxes.iter().zip(found.iter()) {
+        assert_eq!(contacts.len(), tree.query(n).len());
+    }
+}
+
+#[test]
+fn debug_cells_report_occupancy_and_last_query() {
+    let mut tree = QuadTree::new(1024.0, 1024.0);
+    fill_tree(&mut tree, 1000);
+    let cells = tree.debug_cells(&[]);
+    assert_eq!(cells.len(), tree.get_boxes().len());
+    assert_eq!(cells.iter().map(|x| x.num_of_elems).sum::<usize>(), 1000);
+    assert_eq!(cells[0].depth, 0);
+    assert!(cells.iter().all(|x| !x.visited));
+
+    let probe = Box::new(rect_probe(5.0, 5.0));
+    let mut traced: Vec<u32> = Vec::new();
+    assert_eq!(
+        tree.query_traced(&probe, &mut traced).len(),
+        tree.query(&probe).len()
+    );
+    let visited: Vec<DebugCell> = tree
+        .debug_cells(&traced)
+        .into_iter()
+        .filter(|x| x.visited)
+        .collect();
+    assert!(visited.len() > 1);
+    assert!(visited.iter().all(|x| x.u_box.intersects(&probe.get_box())));
+    assert!(visited.iter().any(|x| x.depth == 0));
+}
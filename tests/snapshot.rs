@@ -0,0 +1,230 @@
+#![cfg(feature = "serde")]
+#[cfg(feature = "render")]
+use my_rusted_balls::math::Vec2;
+use my_rusted_balls::objects::*;
+use my_rusted_balls::quadtree::*;
+#[cfg(feature = "render")]
+use raylib::prelude::Color;
+
+fn level(num: u32) -> QuadTree<Rectangle> {
+    fill(QuadTree::new(1024.0, 1024.0), num)
+}
+
+fn fill(mut tree: QuadTree<Rectangle>, num: u32) -> QuadTree<Rectangle> {
+    for n in 0..num {
+        tree.add(
+            RectangleBuilder::new()
+                .coordinate((n * 37 % 1000) as f32, (n * 91 % 1000) as f32)
+                .size(10.0 + (n % 7) as f32, 10.0 + (n % 5) as f32)
+                .name(&format!("rect {}", n))
+                .build(),
+        );
+    }
+    tree
+}
+
+fn query_ids(tree: &QuadTree<Rectangle>, probe: &QuadBox) -> Vec<ObjectId> {
+    let mut ret: Vec<ObjectId> = tree.iter_in_box(probe).map(|(id, _)| id).collect();
+    ret.sort();
+    ret
+}
+
+#[test]
+fn snapshot_restores_structure_and_data() {
+    let mut tree = level(2000);
+    let ids: Vec<ObjectId> = tree.iter().map(|(id, _)| id).collect();
+    for id in ids.iter().step_by(3) {
+        tree.remove(*id);
+    }
+
+    let json = serde_json::to_string(&tree).unwrap();
+    let restored: QuadTree<Rectangle> = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.len(), tree.len());
+    assert_eq!(restored.to_json(), tree.to_json());
+    for (id, elem) in tree.iter() {
+        assert_eq!(restored.get(id).unwrap().to_string(), elem.to_string());
+    }
+    assert!(restored.get(ids[0]).is_none());
+    for n in 0..20 {
+        let probe = QuadBox::new(50.0 * n as f32, 40.0 * n as f32, 80.0, 80.0);
+        assert_eq!(query_ids(&restored, &probe), query_ids(&tree, &probe));
+    }
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+}
+
+#[test]
+fn restored_tree_stays_editable() {
+    let tree = level(500);
+    let mut restored: QuadTree<Rectangle> =
+        serde_json::from_str(&serde_json::to_string(&tree).unwrap()).unwrap();
+    let id = restored.add(
+        RectangleBuilder::new()
+            .coordinate(5.0, 5.0)
+            .size(4.0, 4.0)
+            .build(),
+    );
+    assert!(restored.update(id, QuadBox::new(900.0, 900.0, 4.0, 4.0)));
+    let probe = QuadBox::new(899.0, 899.0, 2.0, 2.0);
+    assert!(query_ids(&restored, &probe).contains(&id));
+    assert!(restored.remove(id).is_some());
+    assert_eq!(restored.len(), 500);
+}
+
+#[test]
+fn corrupt_snapshot_is_rejected() {
+    let tree = level(100);
+    let mut value = serde_json::to_value(&tree).unwrap();
+    value["nodes"][0]["children"] = serde_json::json!(4000);
+    assert!(serde_json::from_value::<QuadTree<Rectangle>>(value).is_err());
+
+    let mut value = serde_json::to_value(&tree).unwrap();
+    value["items"][0]["generation"] = serde_json::json!(7);
+    assert!(serde_json::from_value::<QuadTree<Rectangle>>(value).is_err());
+}
+
+fn snapshot(tree: &QuadTree<Rectangle>) -> serde_json::Value {
+    serde_json::to_value(tree).unwrap()
+}
+
+fn rejects(value: serde_json::Value) {
+    assert!(serde_json::from_value::<QuadTree<Rectangle>>(value).is_err());
+}
+
+#[test]
+fn snapshot_keeps_split_policy() {
+    let builders = [
+        QuadTreeBuilder::new().policy(MedianPolicy {
+            max_depth: 6,
+            max_num_of_elems: 2,
+        }),
+        QuadTreeBuilder::new().policy(MinCellSizePolicy::new(64.0, 3).unwrap().max_depth(5)),
+    ];
+    for builder in builders {
+        let tree = fill(builder.size(1024.0, 1024.0).build(), 300);
+        let mut restored: QuadTree<Rectangle> =
+            serde_json::from_str(&serde_json::to_string(&tree).unwrap()).unwrap();
+        assert_eq!(restored.to_json(), tree.to_json());
+        let mut tree = tree;
+        let extra = level(100);
+        for (_, x) in extra.iter() {
+            tree.add(x.clone());
+            restored.add(x.clone());
+        }
+        assert_eq!(restored.to_json(), tree.to_json());
+    }
+
+    let tree = fill(
+        QuadTreeBuilder::new()
+            .policy(MinCellSizePolicy::new(64.0, 3).unwrap())
+            .build(),
+        300,
+    );
+    let mut value = snapshot(&tree);
+    value["policy"]["MinCellSize"]["min_cell_size"] = serde_json::json!(0.0);
+    rejects(value);
+}
+
+#[derive(Debug)]
+struct EveryElemPolicy;
+
+impl SplitPolicy for EveryElemPolicy {
+    fn should_split(&self, _node_box: &QuadBox, depth: u32, num_of_elems: usize) -> bool {
+        depth < 3 && num_of_elems > 1
+    }
+}
+
+#[test]
+fn custom_policy_is_handed_back_on_restore() {
+    let tree = fill(QuadTreeBuilder::new().policy(EveryElemPolicy).build(), 200);
+    let json = serde_json::to_string(&tree).unwrap();
+    assert!(serde_json::from_str::<QuadTree<Rectangle>>(&json).is_err());
+    let restored: QuadTree<Rectangle> = QuadTree::restore_with_policy(
+        &mut serde_json::Deserializer::from_str(&json),
+        EveryElemPolicy,
+    )
+    .unwrap();
+    assert_eq!(restored.to_json(), tree.to_json());
+}
+
+fn removed_level() -> QuadTree<Rectangle> {
+    let mut tree = level(2000);
+    let ids: Vec<ObjectId> = tree.iter().map(|(id, _)| id).collect();
+    for id in ids.iter().step_by(3) {
+        tree.remove(*id);
+    }
+    tree
+}
+
+fn nodes(value: &serde_json::Value) -> &Vec<serde_json::Value> {
+    value["nodes"].as_array().unwrap()
+}
+
+#[test]
+fn snapshot_with_looping_children_is_rejected() {
+    let value = snapshot(&removed_level());
+    let leaf = serde_json::json!(u32::MAX);
+    let inner = (1..nodes(&value).len())
+        .find(|n| nodes(&value)[*n]["children"] != leaf)
+        .unwrap();
+    let mut looping = value.clone();
+    looping["nodes"][inner]["children"] = value["nodes"][0]["children"].clone();
+    rejects(looping);
+
+    let mut to_root = value;
+    to_root["nodes"][inner]["children"] = serde_json::json!(0);
+    rejects(to_root);
+}
+
+#[test]
+fn snapshot_with_repeated_free_item_is_rejected() {
+    let mut value = snapshot(&removed_level());
+    let free = value["free_items"][0].clone();
+    value["free_items"].as_array_mut().unwrap().push(free);
+    rejects(value);
+}
+
+#[test]
+fn snapshot_with_swapped_entry_is_rejected() {
+    let value = snapshot(&removed_level());
+    let node = nodes(&value)
+        .iter()
+        .position(|x| x["values"].as_array().unwrap().len() >= 2)
+        .unwrap();
+    let mut value = value;
+    value["nodes"][node]["values"][1] = value["nodes"][node]["values"][0].clone();
+    rejects(value);
+}
+
+#[test]
+fn snapshot_with_misplaced_entry_is_rejected() {
+    let mut value = snapshot(&removed_level());
+    let with_values: Vec<usize> = (1..nodes(&value).len())
+        .filter(|n| !nodes(&value)[*n]["values"].as_array().unwrap().is_empty())
+        .collect();
+    let (from, to) = (with_values[0], with_values[with_values.len() - 1]);
+    let entry = value["nodes"][from]["values"]
+        .as_array_mut()
+        .unwrap()
+        .remove(0);
+    value["nodes"][to]["values"]
+        .as_array_mut()
+        .unwrap()
+        .push(entry);
+    rejects(value);
+}
+
+#[cfg(feature = "render")]
+#[test]
+fn circle_round_trips() {
+    let circle = CircleBuilder::new()
+        .coordinate(12.0, 34.0)
+        .radius(5.0)
+        .acel(Vec2 { x: 1.0, y: 2.0 })
+        .color(Color::RED)
+        .build();
+    let json = serde_json::to_string(&circle).unwrap();
+    let restored: Circle = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.coordinate, circle.coordinate);
+    assert_eq!(restored.get_box(), circle.get_box());
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+}